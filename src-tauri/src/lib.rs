@@ -1,13 +1,21 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 use chardetng::EncodingDetector;
 use encoding_rs::Encoding;
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
 use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+use tauri::{AppHandle, Emitter, Manager};
 
 // ─── Allowlist for approved file/folder access (Security) ───
 /// Tracks paths approved by user through dialogs.
@@ -16,13 +24,86 @@ static APPROVED_PATHS: Lazy<Mutex<Vec<PathBuf>>> = Lazy::new(|| {
     Mutex::new(Vec::new())
 });
 
+/// File in the app config dir that keeps approvals across restarts.
+const ALLOWLIST_FILE: &str = "approved-paths.json";
+
+/// Most approvals kept on disk; the least recently approved are dropped first.
+const MAX_PERSISTED_PATHS: usize = 200;
+
+/// Where the allowlist is persisted. Unset until `run()` resolves the config
+/// dir, so approvals made before that (and in tests) stay in memory only.
+static ALLOWLIST_PATH: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| {
+    Mutex::new(None)
+});
+
+/// Write the allowlist to disk, most recent approvals last. Failures are
+/// logged rather than returned: the in-memory allowlist is still authoritative.
+fn persist_allowlist(allowed: &[PathBuf]) {
+    let Some(path) = ALLOWLIST_PATH.lock().ok().and_then(|p| p.clone()) else {
+        return;
+    };
+    let recent = &allowed[allowed.len().saturating_sub(MAX_PERSISTED_PATHS)..];
+    let paths: Vec<String> = recent.iter().map(|p| p.to_string_lossy().to_string()).collect();
+
+    let result = serde_json::to_vec_pretty(&paths)
+        .map_err(|e| e.to_string())
+        .and_then(|json| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            write_atomic(&path, &json).map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        eprintln!("Warning: failed to persist approved paths: {}", e);
+    }
+}
+
+/// Restore approvals saved by `persist_allowlist`, and persist to `path` from
+/// now on. Paths that no longer exist, or that now resolve somewhere else (a
+/// folder replaced by a symlink), are dropped. A corrupt file is overwritten.
+fn load_allowlist(path: PathBuf) -> Result<(), String> {
+    // Persist from now on even if the file is unreadable, so it gets repaired
+    *ALLOWLIST_PATH.lock().map_err(|_| "Allowlist lock poisoned".to_string())? = Some(path.clone());
+
+    let (saved, malformed): (Vec<String>, bool) = match fs::read_to_string(&path) {
+        Ok(raw) => match serde_json::from_str(&raw) {
+            Ok(saved) => (saved, false),
+            Err(e) => {
+                eprintln!("Warning: discarding unreadable {}: {}", path.display(), e);
+                (Vec::new(), true)
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Vec::new(), false),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    let mut allowed = APPROVED_PATHS.lock()
+        .map_err(|_| "Allowlist lock poisoned".to_string())?;
+    let mut dropped = malformed;
+    for entry in &saved {
+        match fs::canonicalize(entry) {
+            Ok(canonical) if canonical == Path::new(entry) => {
+                if !allowed.contains(&canonical) {
+                    allowed.push(canonical);
+                }
+            }
+            _ => dropped = true,
+        }
+    }
+    if dropped {
+        persist_allowlist(&allowed);
+    }
+    Ok(())
+}
+
 fn add_approved_path(canonical: PathBuf) -> Result<(), String> {
     let mut allowed = APPROVED_PATHS.lock()
         .map_err(|_| "Allowlist lock poisoned".to_string())?;
 
-    if !allowed.contains(&canonical) {
-        allowed.push(canonical);
-    }
+    // Re-approving moves an entry to the end, so the persisted cap keeps recent folders
+    allowed.retain(|p| p != &canonical);
+    allowed.push(canonical);
+    persist_allowlist(&allowed);
     Ok(())
 }
 
@@ -58,19 +139,32 @@ fn approve_path_within(path: String, root: String) -> Result<(), String> {
     add_approved_path(canonical)
 }
 
+/// True if `candidate` equals one of `roots` or lies beneath one of them.
+/// Matching is per path component, so `/foo` does not cover `/foobar`.
+fn path_under_any(candidate: &Path, roots: &[PathBuf]) -> bool {
+    roots.iter().any(|root| candidate.starts_with(root))
+}
+
 /// Check if a path is under an approved parent or is approved itself
 fn is_path_allowed(path: &str) -> Result<PathBuf, String> {
     let canonical = fs::canonicalize(path)
         .map_err(|e| format!("Cannot resolve path: {}", e))?;
     
-    let allowed = APPROVED_PATHS.lock()
-        .map_err(|_| "Allowlist lock poisoned".to_string())?;
+    let roots = current_approved_roots()?;
     
     // Check if path is in the allowlist or under an approved folder
-    for approved in allowed.iter() {
-        if canonical.starts_with(approved) || &canonical == approved {
-            return Ok(canonical);
-        }
+    if path_under_any(&canonical, &roots) {
+        return Ok(canonical);
+    }
+
+    // Lexically inside an approved folder but resolving elsewhere: a symlinked
+    // component leads out of it
+    if path_under_any(Path::new(path), &roots) {
+        return Err(format!(
+            "Access denied: {} resolves to {} through a symlink outside the approved folder.",
+            path,
+            canonical.display()
+        ));
     }
     
     Err(format!(
@@ -79,12 +173,148 @@ fn is_path_allowed(path: &str) -> Result<PathBuf, String> {
     ))
 }
 
+/// Approved roots that still canonicalize to themselves. A root that has since
+/// been deleted, or replaced by a symlink to somewhere else, no longer grants
+/// access: what the user approved was the folder, not whatever now sits there.
+fn current_approved_roots() -> Result<Vec<PathBuf>, String> {
+    let allowed = APPROVED_PATHS.lock()
+        .map_err(|_| "Allowlist lock poisoned".to_string())?
+        .clone();
+
+    Ok(allowed
+        .into_iter()
+        .filter(|root| fs::canonicalize(root).is_ok_and(|now| &now == root))
+        .collect())
+}
+
+/// Canonicalize a path that may no longer exist by resolving its parent instead.
+fn canonicalize_missing(path: &str) -> Result<PathBuf, String> {
+    let raw = Path::new(path);
+    let parent = raw.parent()
+        .ok_or_else(|| "Invalid path (no parent directory)".to_string())?;
+    let name = raw.file_name()
+        .ok_or_else(|| "Invalid path (no file name)".to_string())?;
+
+    let canonical_parent = fs::canonicalize(parent)
+        .map_err(|e| format!("Cannot resolve parent path: {}", e))?;
+    Ok(canonical_parent.join(name))
+}
+
+/// After a folder is renamed on disk, rewrite every approved path under
+/// `old_prefix` to live under `new_prefix`, returning the `(old, new)` pairs so
+/// the frontend can fix open tab paths. Only applies to a completed rename: the
+/// old prefix must be gone, the new one must exist, and the old prefix must have
+/// been covered by the allowlist. The new location must already be under an
+/// approved folder (e.g. a subfolder renamed inside an open project), so this
+/// only carries over file approvals and never grants access to anything new.
+#[tauri::command]
+fn remap_paths(old_prefix: String, new_prefix: String) -> Result<Vec<(String, String)>, String> {
+    if Path::new(&old_prefix).exists() {
+        return Err("Old path still exists; nothing was renamed".to_string());
+    }
+    let old_root = canonicalize_missing(&old_prefix)?;
+    let new_root = fs::canonicalize(&new_prefix)
+        .map_err(|e| format!("Cannot resolve new path: {}", e))?;
+
+    if !path_under_any(&new_root, &current_approved_roots()?) {
+        return Err(format!(
+            "Access denied: {} not in approved paths. User must open file/folder first.",
+            new_prefix
+        ));
+    }
+
+    let mut allowed = APPROVED_PATHS.lock()
+        .map_err(|_| "Allowlist lock poisoned".to_string())?;
+
+    if !path_under_any(&old_root, &allowed) {
+        return Err(format!(
+            "Access denied: {} not in approved paths. User must open file/folder first.",
+            old_prefix
+        ));
+    }
+
+    let mut remapped = Vec::new();
+    for approved in allowed.iter_mut() {
+        let Ok(rel) = approved.strip_prefix(&old_root) else {
+            continue;
+        };
+        let moved = if rel.as_os_str().is_empty() {
+            new_root.clone()
+        } else {
+            new_root.join(rel)
+        };
+        remapped.push((
+            approved.to_string_lossy().to_string(),
+            moved.to_string_lossy().to_string(),
+        ));
+        *approved = moved;
+    }
+
+    // Two entries can collapse into one if the new location was already approved
+    let mut seen = Vec::with_capacity(allowed.len());
+    allowed.retain(|p| {
+        if seen.contains(p) {
+            false
+        } else {
+            seen.push(p.clone());
+            true
+        }
+    });
+    persist_allowlist(&allowed);
+
+    Ok(remapped)
+}
+
+/// Remove `path` and every approval beneath it from the allowlist, e.g. when a
+/// project folder is closed. Watchers on the revoked paths are stopped too.
+/// Returns whether anything was removed.
+#[tauri::command]
+fn revoke_path(path: String) -> Result<bool, String> {
+    // The folder may already be gone from disk; its approval should still go
+    let canonical = fs::canonicalize(&path).or_else(|_| canonicalize_missing(&path))?;
+
+    let mut allowed = APPROVED_PATHS.lock()
+        .map_err(|_| "Allowlist lock poisoned".to_string())?;
+    let before = allowed.len();
+    allowed.retain(|p| !p.starts_with(&canonical));
+    let removed = allowed.len() != before;
+    if removed {
+        persist_allowlist(&allowed);
+    }
+    drop(allowed);
+
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+    watchers.retain(|_, watcher| !watcher.roots.iter().any(|root| root.starts_with(&canonical)));
+    drop(watchers);
+
+    // Those watchers kept the prefetched listings fresh; without them the listings go too
+    invalidate_dir_cache(&path);
+    invalidate_dir_cache(&canonical.to_string_lossy());
+    Ok(removed)
+}
+
 /// Clear the allowlist (for testing or session reset)
 #[tauri::command]
 fn clear_approved_paths() -> Result<(), String> {
     let mut allowed = APPROVED_PATHS.lock()
         .map_err(|_| "Allowlist lock poisoned".to_string())?;
     allowed.clear();
+    persist_allowlist(&allowed);
+    drop(allowed);
+
+    // Watchers on formerly approved paths must not keep reporting changes. The
+    // language map lives in the config dir, outside the allowlist, so it stays.
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+    watchers.retain(|id, _| id == LANGUAGE_MAP_WATCH_ID);
+    drop(watchers);
+
+    // Prefetched trees lost their invalidating watchers above
+    DIR_CACHE_GENERATION.fetch_add(1, Ordering::SeqCst);
+    if let Ok(mut cache) = DIR_CACHE.lock() {
+        cache.clear();
+    }
     Ok(())
 }
 
@@ -139,11 +369,33 @@ fn validate_read_dir(path: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// Error returned by commands whose callers need to branch on the failure kind.
+/// `code` is a stable identifier; `message` is the same human-readable text the
+/// `String` errors carry, so `err.message` works for display.
+#[derive(Serialize, Debug)]
+pub struct CommandError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl CommandError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        CommandError { code, message: message.into() }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::new("error", message)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct FileEntry {
     pub name: String,
     pub path: String,
     pub is_dir: bool,
+    /// None for files and for directories that have not been expanded yet.
     pub children: Option<Vec<FileEntry>>,
 }
 
@@ -155,6 +407,17 @@ pub struct FileContent {
     pub file_name: String,
     pub size: u64,
     pub line_ending: String,
+    /// Tab width used when tabs were expanded to spaces on read, so save can re-tabify.
+    pub expanded_tab_width: Option<u8>,
+    /// UTF-16 offsets into `content` where undecodable bytes were replaced, stripped, or marked.
+    pub decode_errors: Vec<usize>,
+    /// True when NFC normalization on read altered the decoded text.
+    pub unicode_normalized: bool,
+    /// SHA-256 of the bytes on disk, for `read_file_delta` change checks and the
+    /// `save_file` conflict guard.
+    pub hash: String,
+    /// True when the file starts with a byte order mark; pass it back to save to keep it.
+    pub has_bom: bool,
 }
 
 fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
@@ -176,6 +439,31 @@ fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
     detector.guess(None, true)
 }
 
+/// Encoding forced for every file read this session, skipping detection.
+/// Set via `force_session_encoding` for codebases known to be uniformly encoded.
+static SESSION_ENCODING: Lazy<Mutex<Option<&'static Encoding>>> = Lazy::new(|| {
+    Mutex::new(None)
+});
+
+/// The encoding to decode file contents with: the session override if set,
+/// otherwise whatever `detect_encoding` guesses.
+fn file_encoding(bytes: &[u8]) -> &'static Encoding {
+    let forced = SESSION_ENCODING.lock().ok().and_then(|forced| *forced);
+    forced.unwrap_or_else(|| detect_encoding(bytes))
+}
+
+/// Force all subsequent file reads to decode with `label`, or restore
+/// automatic detection with `None`.
+#[tauri::command]
+fn force_session_encoding(label: Option<String>) -> Result<(), String> {
+    let encoding = label.as_deref().map(resolve_encoding).transpose()?;
+
+    let mut forced = SESSION_ENCODING.lock()
+        .map_err(|_| "Session encoding lock poisoned".to_string())?;
+    *forced = encoding;
+    Ok(())
+}
+
 fn detect_line_ending(content: &str) -> String {
     if content.contains("\r\n") {
         "CRLF".to_string()
@@ -186,19 +474,347 @@ fn detect_line_ending(content: &str) -> String {
     }
 }
 
+/// Read a file and decode it with the detected encoding.
+fn read_text(path: &Path) -> Result<String, String> {
+    let bytes = fs::read(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let (content, _, _) = file_encoding(&bytes).decode(&bytes);
+    Ok(content.into_owned())
+}
+
+/// Hex SHA-256 of raw file bytes, used to detect on-disk changes.
+fn content_hash(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Return the byte-order mark at the start of `bytes`, if any.
+/// Mirrors the BOM checks in `detect_encoding`.
+fn detect_bom(bytes: &[u8]) -> &'static [u8] {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        &[0xEF, 0xBB, 0xBF]
+    } else if bytes.starts_with(&[0xFF, 0xFE]) {
+        &[0xFF, 0xFE]
+    } else if bytes.starts_with(&[0xFE, 0xFF]) {
+        &[0xFE, 0xFF]
+    } else {
+        &[]
+    }
+}
+
+/// The byte order mark `detect_encoding` recognizes for `encoding`; empty for
+/// encodings that have none.
+fn bom_for(encoding: &'static Encoding) -> &'static [u8] {
+    if encoding == encoding_rs::UTF_8 {
+        &[0xEF, 0xBB, 0xBF]
+    } else if encoding == encoding_rs::UTF_16LE {
+        &[0xFF, 0xFE]
+    } else if encoding == encoding_rs::UTF_16BE {
+        &[0xFE, 0xFF]
+    } else {
+        &[]
+    }
+}
+
+/// Encode text back into `encoding`. encoding_rs only encodes to ASCII-compatible
+/// targets (UTF-16 silently becomes UTF-8), so UTF-16 is handled by hand.
+/// Characters the target cannot represent are an error rather than the HTML
+/// numeric entities encoding_rs would substitute.
+fn encode_content(content: &str, encoding: &'static Encoding) -> Result<Vec<u8>, String> {
+    if encoding == encoding_rs::UTF_16LE {
+        return Ok(content.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect());
+    }
+    if encoding == encoding_rs::UTF_16BE {
+        return Ok(content.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect());
+    }
+
+    let (bytes, _, had_unmappable) = encoding.encode(content);
+    if had_unmappable {
+        let mut buf = [0; 4];
+        let first = content
+            .chars()
+            .find(|c| encoding.encode(c.encode_utf8(&mut buf)).2)
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        return Err(format!(
+            "Text contains characters that cannot be saved as {} (first: '{}', U+{:04X})",
+            encoding.name(),
+            first,
+            first as u32
+        ));
+    }
+    Ok(bytes.into_owned())
+}
+
+/// Split text into lines, keeping each line's own terminator (LF, CRLF, or CR).
+fn split_lines_keep_endings(content: &str) -> Vec<&str> {
+    let bytes = content.as_bytes();
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => {
+                lines.push(&content[start..=i]);
+                start = i + 1;
+            }
+            b'\r' => {
+                let end = if bytes.get(i + 1) == Some(&b'\n') { i + 1 } else { i };
+                lines.push(&content[start..=end]);
+                start = end + 1;
+                i = end;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if start < content.len() {
+        lines.push(&content[start..]);
+    }
+    lines
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ContentAnalysis {
+    pub encoding: String,
+    pub has_bom: bool,
+    pub line_ending: String,
+}
+
+/// Classify an in-memory buffer (clipboard, network) with the same encoding and
+/// line-ending detection used for files.
+#[tauri::command]
+fn analyze_content(bytes: Vec<u8>) -> Result<ContentAnalysis, String> {
+    let encoding = detect_encoding(&bytes);
+    let (content, _, _) = encoding.decode(&bytes);
+
+    Ok(ContentAnalysis {
+        encoding: encoding.name().to_string(),
+        has_bom: !detect_bom(&bytes).is_empty(),
+        line_ending: detect_line_ending(&content),
+    })
+}
+
+/// Markers placed around each U+FFFD under the "mark" replacement policy.
+const DECODE_ERROR_MARK_OPEN: &str = "\u{27E6}";
+const DECODE_ERROR_MARK_CLOSE: &str = "\u{27E7}";
+
+/// What to do with byte sequences that cannot be decoded in the detected encoding.
+#[derive(Clone, Copy, PartialEq)]
+enum ReplacementPolicy {
+    /// Substitute U+FFFD (the default, matching `Encoding::decode`).
+    Keep,
+    /// Drop malformed sequences entirely.
+    Strip,
+    /// Substitute U+FFFD wrapped in sentinel markers.
+    Mark,
+}
+
+impl ReplacementPolicy {
+    fn parse(label: Option<&str>) -> Result<Self, String> {
+        match label.map(str::trim) {
+            None | Some("") | Some("keep") => Ok(Self::Keep),
+            Some("strip") => Ok(Self::Strip),
+            Some("mark") => Ok(Self::Mark),
+            Some(other) => Err(format!("Unknown replacement policy: {}", other)),
+        }
+    }
+}
+
+/// Decode `bytes`, applying `policy` to malformed sequences. Returns the text and
+/// the byte offsets in it where data was lost.
+fn decode_with_policy(
+    bytes: &[u8],
+    encoding: &'static Encoding,
+    policy: ReplacementPolicy,
+) -> (String, Vec<usize>) {
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut out = String::new();
+    let mut errors = Vec::new();
+    let mut input = bytes;
+
+    loop {
+        if let Some(needed) = decoder.max_utf8_buffer_length_without_replacement(input.len()) {
+            out.reserve(needed);
+        }
+        let (result, read) = decoder.decode_to_string_without_replacement(input, &mut out, true);
+        input = &input[read..];
+
+        match result {
+            encoding_rs::DecoderResult::InputEmpty => break,
+            encoding_rs::DecoderResult::OutputFull => continue,
+            encoding_rs::DecoderResult::Malformed(_, _) => {
+                errors.push(out.len());
+                match policy {
+                    ReplacementPolicy::Keep => out.push('\u{FFFD}'),
+                    ReplacementPolicy::Strip => {}
+                    ReplacementPolicy::Mark => {
+                        out.push_str(DECODE_ERROR_MARK_OPEN);
+                        out.push('\u{FFFD}');
+                        out.push_str(DECODE_ERROR_MARK_CLOSE);
+                    }
+                }
+            }
+        }
+    }
+
+    (out, errors)
+}
+
+/// Convert sorted byte offsets into UTF-16 code unit offsets, which is what
+/// JavaScript string indices (and so the editor) use.
+fn byte_offsets_to_utf16(content: &str, offsets: &[usize]) -> Vec<usize> {
+    let mut result = Vec::with_capacity(offsets.len());
+    let mut pending = offsets.iter().peekable();
+    let mut utf16 = 0;
+
+    for (index, ch) in content.char_indices() {
+        while pending.peek().is_some_and(|&&offset| offset <= index) {
+            pending.next();
+            result.push(utf16);
+        }
+        utf16 += ch.len_utf16();
+    }
+    result.extend(pending.map(|_| utf16));
+    result
+}
+
+/// NFC-normalize `content`, returning whether anything changed. Text is normalized
+/// in segments split at the sorted byte `offsets`, which are shifted in place; the
+/// replacement characters at those offsets never compose, so results are unaffected.
+fn normalize_nfc(content: &str, offsets: &mut [usize]) -> (String, bool) {
+    let mut out = String::with_capacity(content.len());
+    let mut start = 0;
+
+    for offset in offsets.iter_mut() {
+        out.extend(content[start..*offset].nfc());
+        start = *offset;
+        *offset = out.len();
+    }
+    out.extend(content[start..].nfc());
+
+    let changed = out != content;
+    (out, changed)
+}
+
+/// Replace tabs with spaces up to the next tab stop. Columns reset at every line break.
+/// Sorted byte `offsets` into `content` are shifted in place to stay on the same text.
+fn expand_tab_stops(content: &str, width: usize, offsets: &mut [usize]) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut column = 0;
+    let mut next_offset = 0;
+
+    for (index, ch) in content.char_indices() {
+        while next_offset < offsets.len() && offsets[next_offset] == index {
+            offsets[next_offset] = out.len();
+            next_offset += 1;
+        }
+
+        match ch {
+            '\t' => {
+                let spaces = width - (column % width);
+                out.extend(std::iter::repeat_n(' ', spaces));
+                column += spaces;
+            }
+            '\n' | '\r' => {
+                out.push(ch);
+                column = 0;
+            }
+            _ => {
+                out.push(ch);
+                column += 1;
+            }
+        }
+    }
+
+    for offset in &mut offsets[next_offset..] {
+        *offset = out.len();
+    }
+    out
+}
+
+/// Default for `READ_SIZE_LIMIT`.
+const DEFAULT_READ_SIZE_LIMIT: u64 = 50 * 1024 * 1024;
+
+/// Largest file `read_file` will load, in bytes. Adjustable with `set_read_size_limit`.
+static READ_SIZE_LIMIT: AtomicU64 = AtomicU64::new(DEFAULT_READ_SIZE_LIMIT);
+
+/// Raise or lower the size above which `read_file` refuses with `file_too_large`,
+/// or restore the default with `None`.
+#[tauri::command]
+fn set_read_size_limit(bytes: Option<u64>) -> Result<(), String> {
+    if bytes == Some(0) {
+        return Err("Size limit must be greater than zero".to_string());
+    }
+    READ_SIZE_LIMIT.store(bytes.unwrap_or(DEFAULT_READ_SIZE_LIMIT), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Read and decode a text file. Fails with code `file_too_large` above the read
+/// size limit and `binary_file` when the head of the file looks binary, so the
+/// UI can offer a hex view or a large-file prompt instead of a garbled buffer.
+/// `encoding_override` skips detection for "reopen with encoding".
 #[tauri::command]
-fn read_file(path: String) -> Result<FileContent, String> {
+fn read_file(
+    path: String,
+    expand_tabs: Option<u8>,
+    replacement_policy: Option<String>,
+    normalize_unicode: Option<bool>,
+    encoding_override: Option<String>,
+) -> Result<FileContent, CommandError> {
     // Validate path before reading
     validate_file_path(&path)?;
+
+    if expand_tabs == Some(0) {
+        return Err("Tab width must be greater than zero".to_string().into());
+    }
+    let policy = ReplacementPolicy::parse(replacement_policy.as_deref())?;
+    let forced_encoding = encoding_override.as_deref().map(resolve_encoding).transpose()?;
     
     let file_path = Path::new(&path);
-    let bytes = fs::read(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
     let metadata = fs::metadata(file_path).map_err(|e| format!("Failed to get metadata: {}", e))?;
+    let limit = READ_SIZE_LIMIT.load(Ordering::Relaxed);
+    if metadata.len() > limit {
+        return Err(CommandError::new(
+            "file_too_large",
+            format!("File is too large to open ({} bytes, limit {} bytes)", metadata.len(), limit),
+        ));
+    }
 
-    let encoding = detect_encoding(&bytes);
-    let (content, _, _) = encoding.decode(&bytes);
+    let bytes = fs::read(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
+    // An explicit encoding, per file or for the session, means the caller knows
+    // it is text (BOM-less UTF-16 is full of NULs)
+    let forced_encoding = forced_encoding.or_else(|| SESSION_ENCODING.lock().ok().and_then(|forced| *forced));
+    let sample = &bytes[..bytes.len().min(BINARY_SAMPLE_BYTES as usize)];
+    if forced_encoding.is_none() && is_binary_sample(sample) {
+        return Err(CommandError::new("binary_file", format!("File appears to be binary: {}", path)));
+    }
+
+    let encoding = forced_encoding.unwrap_or_else(|| detect_encoding(&bytes));
+    let (content, mut error_offsets) = decode_with_policy(&bytes, encoding, policy);
+
+    // Off by default so the buffer matches the bytes on disk exactly
+    let (content, unicode_normalized) = if normalize_unicode.unwrap_or(false) {
+        normalize_nfc(&content, &mut error_offsets)
+    } else {
+        (content, false)
+    };
 
     let line_ending = detect_line_ending(&content);
+
+    // Only record an expansion when there were tabs to expand
+    let (content, expanded_tab_width) = match expand_tabs {
+        Some(width) if content.contains('\t') => {
+            (expand_tab_stops(&content, width as usize, &mut error_offsets), Some(width))
+        }
+        _ => (content, None),
+    };
+    let decode_errors = byte_offsets_to_utf16(&content, &error_offsets);
+
     let file_name = file_path
         .file_name()
         .unwrap_or_default()
@@ -206,113 +822,2528 @@ fn read_file(path: String) -> Result<FileContent, String> {
         .to_string();
 
     Ok(FileContent {
-        content: content.to_string(),
+        content,
         encoding: encoding.name().to_string(),
-        path: path,
+        path,
         file_name,
         size: metadata.len(),
         line_ending,
+        expanded_tab_width,
+        decode_errors,
+        unicode_normalized,
+        hash: content_hash(&bytes),
+        has_bom: !detect_bom(&bytes).is_empty(),
     })
 }
 
-#[tauri::command]
-fn save_file(path: String, content: String) -> Result<(), String> {
-    // Validate path against write allowlist (same as save_file_as)
-    validate_write_path(&path)?;
-    fs::write(&path, content.as_bytes()).map_err(|e| format!("Failed to save file: {}", e))
+/// Average silent reading speed used for the reading time estimate.
+const READING_WORDS_PER_MINUTE: usize = 200;
+
+#[derive(Serialize, Deserialize)]
+pub struct TextStats {
+    pub words: usize,
+    pub characters: usize,
+    pub lines: usize,
+    /// Estimated reading time, only for prose (markdown and plain text).
+    pub reading_minutes: Option<usize>,
 }
 
+/// Word, character, and line counts for a status bar, without shipping the
+/// whole file to the frontend.
 #[tauri::command]
-fn save_file_as(path: String, content: String) -> Result<(), String> {
-    // Validate that parent directory exists and is writable
-    validate_write_path(&path)?;
-    
-    fs::write(&path, content.as_bytes()).map_err(|e| format!("Failed to save file: {}", e))
+fn text_stats(path: String) -> Result<TextStats, String> {
+    validate_file_path(&path)?;
+
+    let file_path = Path::new(&path);
+    let content = read_text(file_path)?;
+    let words = content.split_whitespace().count();
+
+    let file_name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let is_prose = matches!(get_file_language(file_name).as_str(), "markdown" | "plaintext");
+
+    Ok(TextStats {
+        words,
+        characters: content.chars().count(),
+        lines: split_lines_keep_endings(&content).len(),
+        reading_minutes: is_prose.then(|| words.div_ceil(READING_WORDS_PER_MINUTE)),
+    })
 }
 
-fn build_file_tree(dir: &Path, depth: u32, max_depth: u32) -> Vec<FileEntry> {
-    if depth > max_depth {
-        return vec![];
+/// How far into a file to look for a charset declaration. HTML requires the
+/// meta tag within the first 1024 bytes; XML declarations come first.
+const DECLARATION_SCAN_BYTES: usize = 4096;
+
+/// Pull the value following `key` (e.g. `charset=`), with or without quotes.
+fn extract_attribute_value<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    let start = text.find(key)? + key.len();
+    let rest = text[start..].trim_start();
+    let rest = rest.strip_prefix(['"', '\'']).unwrap_or(rest);
+    let end = rest
+        .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '>' || c == '?' || c.is_whitespace())
+        .unwrap_or(rest.len());
+    let value = &rest[..end];
+    (!value.is_empty()).then_some(value)
+}
+
+/// Find an HTML `charset=` or XML `encoding=` declaration in the head of a document.
+fn find_declared_charset(head: &str) -> Option<String> {
+    let lower = head.to_lowercase();
+
+    if lower.trim_start().starts_with("<?xml") {
+        let decl_end = lower.find("?>").unwrap_or(lower.len());
+        if let Some(value) = extract_attribute_value(&lower[..decl_end], "encoding=") {
+            return Some(value.to_string());
+        }
     }
 
-    let mut entries: Vec<FileEntry> = Vec::new();
+    lower
+        .match_indices("<meta")
+        .filter_map(|(idx, _)| {
+            let tag_end = lower[idx..].find('>').map(|e| idx + e).unwrap_or(lower.len());
+            extract_attribute_value(&lower[idx..tag_end], "charset=")
+        })
+        .map(str::to_string)
+        .next()
+}
 
-    if let Ok(read_dir) = fs::read_dir(dir) {
-        let mut items: Vec<_> = read_dir.filter_map(|e| e.ok()).collect();
-        items.sort_by(|a, b| {
-            let a_is_dir = a.file_type().map(|t| t.is_dir()).unwrap_or(false);
-            let b_is_dir = b.file_type().map(|t| t.is_dir()).unwrap_or(false);
-            b_is_dir.cmp(&a_is_dir).then(
-                a.file_name()
-                    .to_string_lossy()
-                    .to_lowercase()
-                    .cmp(&b.file_name().to_string_lossy().to_lowercase()),
-            )
-        });
+/// Compare an HTML/XML charset declaration with the encoding the bytes actually
+/// use, returning a warning when they disagree. Pure-ASCII content is compatible
+/// with any ASCII-based declaration and never warns.
+#[tauri::command]
+fn check_encoding_declaration(path: String) -> Result<Option<String>, String> {
+    validate_file_path(&path)?;
 
-        for item in items {
-            let name = item.file_name().to_string_lossy().to_string();
+    let bytes = fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let detected = detect_encoding(&bytes);
+    let head_len = bytes.len().min(DECLARATION_SCAN_BYTES);
+    let (head, _, _) = detected.decode(&bytes[..head_len]);
 
-            // Skip hidden files/dirs and common non-useful dirs
-            if name.starts_with('.') || name == "node_modules" || name == "target" {
-                continue;
-            }
+    let Some(label) = find_declared_charset(&head) else {
+        return Ok(None);
+    };
+    let Some(declared) = Encoding::for_label(label.as_bytes()) else {
+        return Ok(Some(format!("Declared charset '{}' is not a recognized encoding", label)));
+    };
 
-            let path = item.path();
-            let is_dir = item.file_type().map(|t| t.is_dir()).unwrap_or(false);
+    if declared == detected || (bytes.is_ascii() && declared.is_ascii_compatible()) {
+        return Ok(None);
+    }
 
-            let children = if is_dir {
-                Some(build_file_tree(&path, depth + 1, max_depth))
-            } else {
-                None
-            };
+    Ok(Some(format!(
+        "Declared charset '{}' does not match detected encoding '{}'",
+        declared.name(),
+        detected.name()
+    )))
+}
 
-            entries.push(FileEntry {
-                name,
-                path: path.to_string_lossy().to_string(),
-                is_dir,
-                children,
-            });
+/// Unescape the body of a double-quoted dotenv value.
+fn unescape_env_value(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
         }
     }
+    out
+}
 
-    entries
+/// Parse one `KEY=value` line. Supports an `export ` prefix, single quotes
+/// (literal), double quotes (with escapes), and trailing ` # comments` on
+/// unquoted values. Returns None for blank, comment, or malformed lines.
+fn parse_env_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line);
+
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+        return None;
+    }
+
+    let value = value.trim();
+    let parsed = if let Some(rest) = value.strip_prefix('"') {
+        unescape_env_value(&rest[..rest.rfind('"')?])
+    } else if let Some(rest) = value.strip_prefix('\'') {
+        rest[..rest.rfind('\'')?].to_string()
+    } else {
+        match value.find(" #") {
+            Some(idx) => value[..idx].trim_end().to_string(),
+            None => value.to_string(),
+        }
+    };
+
+    Some((key.to_string(), parsed))
 }
 
+/// Read a dotenv-style file into ordered key/value pairs. Malformed lines are skipped.
 #[tauri::command]
-fn list_directory(path: String) -> Result<Vec<FileEntry>, String> {
-    // Check allowlist first (security)
-    validate_read_dir(&path)?;
-    
-    let dir_path = Path::new(&path);
-    Ok(build_file_tree(dir_path, 0, 10))
+fn parse_env_file(path: String) -> Result<Vec<(String, String)>, String> {
+    validate_file_path(&path)?;
+
+    let content = read_text(Path::new(&path))?;
+    Ok(content.lines().filter_map(parse_env_line).collect())
+}
+
+/// Read and parse a JSON file. serde_json errors already carry the line and column.
+fn parse_json_file(path: &str) -> Result<serde_json::Value, String> {
+    let content = read_text(Path::new(path))?;
+    serde_json::from_str(&content).map_err(|e| format!("Invalid JSON: {}", e))
 }
 
+/// Pretty-print a JSON file with `indent` spaces, keeping the original key order.
 #[tauri::command]
-fn get_file_language(file_name: String) -> String {
-    let ext = Path::new(&file_name)
-        .extension()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_lowercase();
+fn format_json(path: String, indent: u8) -> Result<String, String> {
+    validate_file_path(&path)?;
+    let value = parse_json_file(&path)?;
 
-    match ext.as_str() {
-        "rs" => "rust",
-        "js" | "mjs" | "cjs" => "javascript",
-        "ts" | "mts" | "cts" => "typescript",
-        "jsx" => "jsx",
-        "tsx" => "tsx",
-        "py" | "pyw" => "python",
-        "java" => "java",
-        "c" => "c",
-        "cpp" | "cc" | "cxx" | "c++" => "cpp",
-        "h" | "hpp" | "hxx" => "cpp",
-        "cs" => "csharp",
-        "go" => "go",
-        "rb" => "ruby",
-        "php" => "php",
-        "swift" => "swift",
-        "kt" | "kts" => "kotlin",
+    let indent = " ".repeat(indent as usize);
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut out = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+    value
+        .serialize(&mut serializer)
+        .map_err(|e| format!("Failed to format JSON: {}", e))?;
+
+    String::from_utf8(out).map_err(|e| format!("Failed to format JSON: {}", e))
+}
+
+/// Re-serialize a JSON file with no insignificant whitespace.
+#[tauri::command]
+fn minify_json(path: String) -> Result<String, String> {
+    validate_file_path(&path)?;
+    let value = parse_json_file(&path)?;
+
+    serde_json::to_string(&value).map_err(|e| format!("Failed to minify JSON: {}", e))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FormatError {
+    /// 1-based line, or None when the parser gave no position.
+    pub line: Option<usize>,
+    /// 1-based column in characters.
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+/// Convert a byte offset into 1-based (line, column) coordinates.
+fn offset_to_line_col(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+fn validate_json(content: &str) -> Vec<FormatError> {
+    match serde_json::from_str::<serde_json::Value>(content) {
+        Ok(_) => Vec::new(),
+        Err(e) => vec![FormatError {
+            line: Some(e.line()),
+            column: Some(e.column()),
+            message: e.to_string(),
+        }],
+    }
+}
+
+fn validate_toml(content: &str) -> Vec<FormatError> {
+    match toml::from_str::<toml::Table>(content) {
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            let position = e.span().map(|span| offset_to_line_col(content, span.start));
+            vec![FormatError {
+                line: position.map(|(line, _)| line),
+                column: position.map(|(_, column)| column),
+                message: e.message().to_string(),
+            }]
+        }
+    }
+}
+
+/// Validate every document in a (possibly multi-document) YAML stream.
+fn validate_yaml(content: &str) -> Vec<FormatError> {
+    serde_yaml::Deserializer::from_str(content)
+        .filter_map(|document| serde_yaml::Value::deserialize(document).err())
+        .map(|e| FormatError {
+            line: e.location().map(|l| l.line()),
+            column: e.location().map(|l| l.column()),
+            message: e.to_string(),
+        })
+        .collect()
+}
+
+/// Check a config file against its format (JSON, TOML, or YAML, chosen by
+/// `get_file_language`) and return positioned errors for inline markers.
+#[tauri::command]
+fn validate_format(path: String) -> Result<Vec<FormatError>, String> {
+    validate_file_path(&path)?;
+
+    let file_name = Path::new(&path).file_name().unwrap_or_default().to_string_lossy().to_string();
+    let language = get_file_language(file_name);
+    let content = read_text(Path::new(&path))?;
+
+    match language.as_str() {
+        "json" => Ok(validate_json(&content)),
+        "toml" => Ok(validate_toml(&content)),
+        "yaml" => Ok(validate_yaml(&content)),
+        other => Err(format!("No format validator for language: {}", other)),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DiffHunk {
+    /// 1-based first line of the hunk in the previous version.
+    pub old_start: usize,
+    pub old_lines: usize,
+    /// 1-based first line of the hunk in the current file.
+    pub new_start: usize,
+    pub new_lines: usize,
+    /// Replacement text for the hunk, line endings included.
+    pub lines: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileDelta {
+    pub changed: bool,
+    pub hash: String,
+    /// Full current content, present only when the file changed.
+    pub content: Option<String>,
+    /// Line-level hunks against `previous_content`, when it was supplied.
+    pub hunks: Vec<DiffHunk>,
+}
+
+/// Re-read a watched file and describe what changed since the editor's copy.
+/// When the hash still matches nothing is returned; otherwise the new content is
+/// sent along with line hunks against `previous_content` so the UI can patch only
+/// the changed regions and keep cursor and fold state.
+#[tauri::command]
+fn read_file_delta(
+    path: String,
+    previous_hash: String,
+    previous_content: Option<String>,
+) -> Result<FileDelta, String> {
+    validate_file_path(&path)?;
+
+    let bytes = fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let hash = content_hash(&bytes);
+    if hash.eq_ignore_ascii_case(previous_hash.trim()) {
+        return Ok(FileDelta { changed: false, hash, content: None, hunks: Vec::new() });
+    }
+
+    let (content, _, _) = file_encoding(&bytes).decode(&bytes);
+    let content = content.into_owned();
+
+    let hunks = match previous_content {
+        Some(previous) => {
+            let diff = similar::TextDiff::from_lines(previous.as_str(), content.as_str());
+            diff.grouped_ops(0)
+                .iter()
+                .map(|group| {
+                    let old_range = group.first().map(|op| op.old_range().start).unwrap_or(0)
+                        ..group.last().map(|op| op.old_range().end).unwrap_or(0);
+                    let new_range = group.first().map(|op| op.new_range().start).unwrap_or(0)
+                        ..group.last().map(|op| op.new_range().end).unwrap_or(0);
+                    DiffHunk {
+                        old_start: old_range.start + 1,
+                        old_lines: old_range.len(),
+                        new_start: new_range.start + 1,
+                        new_lines: new_range.len(),
+                        lines: diff.new_slices()[new_range].iter().map(|l| l.to_string()).collect(),
+                    }
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+
+    Ok(FileDelta { changed: true, hash, content: Some(content), hunks })
+}
+
+/// Bytes sampled from the head of a file to estimate its shape.
+const COMPLEXITY_SAMPLE_BYTES: u64 = 64 * 1024;
+/// A sampled line this long almost always means minified or generated output.
+const MINIFIED_LINE_CHARS: usize = 1000;
+/// Average line length above which a sample counts as minified.
+const MINIFIED_AVERAGE_CHARS: usize = 300;
+/// Estimated line count above which the editor is likely to lag.
+const LARGE_FILE_LINES: usize = 200_000;
+/// Size above which opening is worth a confirmation regardless of shape.
+const LARGE_FILE_BYTES: u64 = 20 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct FileComplexity {
+    pub size: u64,
+    pub sampled_bytes: usize,
+    /// Line count extrapolated from the sample; exact when the whole file was sampled.
+    pub estimated_lines: usize,
+    /// Longest line seen in the sample, in characters.
+    pub longest_line: usize,
+    pub appears_minified: bool,
+    /// True when the UI should ask before opening.
+    pub should_warn: bool,
+}
+
+/// Probe a file's head to estimate line count, longest line, and minification
+/// without reading it all, so the UI can prompt before opening something huge.
+#[tauri::command]
+fn estimate_file_complexity(path: String) -> Result<FileComplexity, String> {
+    validate_file_path(&path)?;
+
+    let file_path = Path::new(&path);
+    let size = fs::metadata(file_path)
+        .map_err(|e| format!("Failed to get metadata: {}", e))?
+        .len();
+    let head = read_head(file_path, COMPLEXITY_SAMPLE_BYTES)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    let (sample, _, _) = file_encoding(&head).decode(&head);
+
+    let lines = split_lines_keep_endings(&sample);
+    let line_count = lines.len().max(1);
+    let longest_line = lines
+        .iter()
+        .map(|line| line.trim_end_matches(['\r', '\n']).chars().count())
+        .max()
+        .unwrap_or(0);
+    let average = sample.chars().count() / line_count;
+
+    let estimated_lines = if head.is_empty() || head.len() as u64 >= size {
+        lines.len()
+    } else {
+        ((size as f64 / head.len() as f64) * line_count as f64).round() as usize
+    };
+    let appears_minified = longest_line >= MINIFIED_LINE_CHARS || average >= MINIFIED_AVERAGE_CHARS;
+
+    Ok(FileComplexity {
+        size,
+        sampled_bytes: head.len(),
+        estimated_lines,
+        longest_line,
+        appears_minified,
+        should_warn: size >= LARGE_FILE_BYTES || estimated_lines >= LARGE_FILE_LINES || appears_minified,
+    })
+}
+
+/// Turn editor text into the bytes written to disk. `encoding`, `line_ending`, and
+/// `has_bom` are usually the values `read_file` reported; `None` leaves the text
+/// as-is in UTF-8, and unknown values are rejected rather than silently ignored.
+fn prepare_save_bytes(
+    content: &str,
+    encoding: Option<&str>,
+    line_ending: Option<&str>,
+    has_bom: bool,
+) -> Result<Vec<u8>, String> {
+    let normalized;
+    let content = match line_ending {
+        Some(style) => {
+            normalized = normalize_line_endings(content, style)?;
+            normalized.as_str()
+        }
+        None => content,
+    };
+    // Don't double up if the buffer still carries the BOM as a leading U+FEFF
+    let content = if has_bom { content.strip_prefix('\u{FEFF}').unwrap_or(content) } else { content };
+
+    let encoding = encoding.map(resolve_encoding).transpose()?.unwrap_or(encoding_rs::UTF_8);
+    let mut bytes = if has_bom { bom_for(encoding).to_vec() } else { Vec::new() };
+    bytes.extend(encode_content(content, encoding)?);
+    Ok(bytes)
+}
+
+static NEXT_TEMP_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Replace `path` with `bytes` without ever leaving it truncated: write a sibling
+/// temp file (same directory, so the rename stays on one filesystem), flush it,
+/// copy over the original's permissions, then rename it into place. A read-only
+/// original is refused, as a plain write would be, rather than renamed over.
+/// The rename gives the file a new identity; `get_file_id` maps it back.
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let original = fs::metadata(path).ok();
+    if original.as_ref().is_some_and(|m| m.permissions().readonly()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "file is read-only"));
+    }
+    let previous_id = original.as_ref().and_then(|_| file_identity(path).ok());
+
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(
+        ".{}.{}-{}.tmp",
+        name,
+        std::process::id(),
+        NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        drop(file);
+
+        if let Some(metadata) = &original {
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        fs::rename(&temp, path)
+    })();
+
+    match &result {
+        Ok(()) => {
+            if let (Some(previous), Ok(current)) = (previous_id, file_identity(path)) {
+                carry_file_id(previous, current);
+            }
+        }
+        Err(_) => {
+            let _ = fs::remove_file(&temp);
+        }
+    }
+    result
+}
+
+/// Where a write to `path` should land, and whether it already exists. An existing
+/// file must itself resolve inside the allowlist: this accepts files opened
+/// individually and rejects symlinks that point outside approved folders. New
+/// files fall back to the parent check used by save_file_as.
+fn resolve_save_target(path: &str) -> Result<(PathBuf, bool), String> {
+    if Path::new(path).exists() {
+        validate_file_path(path)?;
+        // Replace the file a symlink points at rather than the link itself
+        let target = fs::canonicalize(path).map_err(|e| format!("Cannot resolve path: {}", e))?;
+        Ok((target, true))
+    } else {
+        validate_write_path(path)?;
+        Ok((PathBuf::from(path), false))
+    }
+}
+
+/// Write an editor buffer back to disk. Pass the `hash` from `read_file` as
+/// `expected_hash` to refuse with code `conflict` when the file changed (or was
+/// deleted) on disk since it was read; `force` skips that check.
+#[tauri::command]
+fn save_file(
+    path: String,
+    content: String,
+    encoding: Option<String>,
+    line_ending: Option<String>,
+    has_bom: Option<bool>,
+    expected_hash: Option<String>,
+    force: Option<bool>,
+) -> Result<(), CommandError> {
+    let (target, exists) = resolve_save_target(&path)?;
+
+    if let Some(expected) = expected_hash.filter(|_| !force.unwrap_or(false)) {
+        let current = if exists {
+            let bytes = fs::read(&target).map_err(|e| format!("Failed to read file: {}", e))?;
+            Some(content_hash(&bytes))
+        } else {
+            None
+        };
+        if !current.is_some_and(|hash| hash.eq_ignore_ascii_case(expected.trim())) {
+            let what = if exists { "changed on disk" } else { "was deleted" };
+            return Err(CommandError::new(
+                "conflict",
+                format!("{} {} since it was opened", path, what),
+            ));
+        }
+    }
+
+    let bytes = prepare_save_bytes(
+        &content,
+        encoding.as_deref(),
+        line_ending.as_deref(),
+        has_bom.unwrap_or(false),
+    )?;
+    write_atomic(&target, &bytes).map_err(|e| format!("Failed to save file: {}", e).into())
+}
+
+#[tauri::command]
+fn save_file_as(
+    path: String,
+    content: String,
+    encoding: Option<String>,
+    line_ending: Option<String>,
+    has_bom: Option<bool>,
+) -> Result<(), String> {
+    // Validate that parent directory exists and is writable
+    validate_write_path(&path)?;
+
+    let bytes = prepare_save_bytes(
+        &content,
+        encoding.as_deref(),
+        line_ending.as_deref(),
+        has_bom.unwrap_or(false),
+    )?;
+    write_atomic(Path::new(&path), &bytes).map_err(|e| format!("Failed to save file: {}", e))
+}
+
+/// Upper bound on `-N` suffixes tried before giving up on a unique name.
+const MAX_UNIQUE_NAME_ATTEMPTS: usize = 1000;
+
+/// Reject names that would escape the target directory.
+fn validate_plain_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(format!("Invalid file name: {}", name));
+    }
+    Ok(())
+}
+
+/// `notes.txt` -> `notes-2.txt`; the first attempt (`n == 0`) is the name itself.
+fn numbered_name(base_name: &str, n: usize) -> String {
+    if n == 0 {
+        return base_name.to_string();
+    }
+    let path = Path::new(base_name);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => format!("{}-{}.{}", stem.to_string_lossy(), n, ext.to_string_lossy()),
+        _ => format!("{}-{}", base_name, n),
+    }
+}
+
+/// Save into `dir` under `base_name`, or the first free `base-N.ext` variant if
+/// taken, returning the path actually written. Each candidate is claimed with
+/// `create_new`, so there is no window between checking a name and writing it.
+#[tauri::command]
+fn save_file_unique(dir: String, base_name: String, content: String) -> Result<String, String> {
+    validate_read_dir(&dir)?;
+    validate_plain_name(&base_name)?;
+
+    for n in 0..MAX_UNIQUE_NAME_ATTEMPTS {
+        let candidate = Path::new(&dir).join(numbered_name(&base_name, n));
+        let candidate_str = candidate.to_string_lossy().to_string();
+        validate_write_path(&candidate_str)?;
+
+        match fs::OpenOptions::new().write(true).create_new(true).open(&candidate) {
+            Ok(mut file) => {
+                file.write_all(content.as_bytes())
+                    .map_err(|e| format!("Failed to save file: {}", e))?;
+                return Ok(candidate_str);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Failed to save file: {}", e)),
+        }
+    }
+
+    Err(format!("No available name for {} in {}", base_name, dir))
+}
+
+/// Map a creation failure to `already_exists` when something is already there.
+fn creation_error(kind: &str, path: &str, e: std::io::Error) -> CommandError {
+    if e.kind() == std::io::ErrorKind::AlreadyExists {
+        CommandError::new("already_exists", format!("{} already exists: {}", kind, path))
+    } else {
+        CommandError::from(format!("Failed to create {}: {}", kind.to_lowercase(), e))
+    }
+}
+
+/// Create an empty file in an approved folder. Never replaces an existing entry.
+#[tauri::command]
+fn create_file(path: String) -> Result<(), CommandError> {
+    validate_write_path(&path)?;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| creation_error("File", &path, e))?;
+    invalidate_dir_caches_containing(&canonicalize_missing(&path)?);
+    Ok(())
+}
+
+/// Create one new folder in an approved folder. Missing intermediate folders are
+/// an error rather than created, so nothing outside the checked parent appears.
+#[tauri::command]
+fn create_directory(path: String) -> Result<(), CommandError> {
+    validate_write_path(&path)?;
+
+    fs::create_dir(&path).map_err(|e| creation_error("Directory", &path, e))?;
+    invalidate_dir_caches_containing(&canonicalize_missing(&path)?);
+    Ok(())
+}
+
+/// Create a new file with initial content, approve it, and return it ready for
+/// editing: create + approve + read in one round trip. Fails with code
+/// `already_exists` if the file exists, like `create_file`.
+#[tauri::command]
+fn create_and_open(path: String, content: String) -> Result<FileContent, CommandError> {
+    validate_write_path(&path)?;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| creation_error("File", &path, e))?;
+    file.write_all(content.as_bytes())
+        .map_err(|e| format!("Failed to write file: {}", e))?;
+    drop(file);
+
+    approve_path(path.clone())?;
+    invalidate_dir_caches_containing(&canonicalize_missing(&path)?);
+    read_file(path, None, None, None, None)
+}
+
+/// Resolve an encoding label, refusing to silently fall back on unknown names.
+fn resolve_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("Unknown encoding: {}", label))
+}
+
+/// Rewrite every line break as `style` ("LF", "CRLF", or "CR"). Mixed endings are
+/// collapsed to `\n` first so `\r\n` never turns into `\r\r\n`.
+fn normalize_line_endings(content: &str, style: &str) -> Result<String, String> {
+    let target = match style {
+        "LF" => "\n",
+        "CRLF" => "\r\n",
+        "CR" => "\r",
+        other => return Err(format!("Unknown line ending: {}", other)),
+    };
+
+    let collapsed = content.replace("\r\n", "\n").replace('\r', "\n");
+    Ok(if target == "\n" {
+        collapsed
+    } else {
+        collapsed.replace('\n', target)
+    })
+}
+
+/// Report whether saving `content` with the given encoding, line ending and BOM
+/// would produce different bytes than the file currently on disk. The bytes are
+/// built exactly as `save_file` builds them.
+#[tauri::command]
+fn save_would_change(
+    path: String,
+    content: String,
+    encoding: String,
+    line_ending: String,
+    has_bom: Option<bool>,
+) -> Result<bool, String> {
+    // Checked like save_file, so an escaping symlink can't be probed for its contents
+    let (target, _) = resolve_save_target(&path)?;
+
+    let proposed = prepare_save_bytes(
+        &content,
+        Some(&encoding),
+        Some(&line_ending),
+        has_bom.unwrap_or(false),
+    )?;
+
+    match fs::read(&target) {
+        Ok(current) => Ok(current != proposed),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(format!("Failed to read file: {}", e)),
+    }
+}
+
+/// Split a large text file into numbered chunks of `lines_per_file` lines, written
+/// next to the source with the same encoding, BOM, and line endings.
+#[tauri::command]
+fn split_file(path: String, lines_per_file: usize) -> Result<Vec<String>, String> {
+    validate_file_path(&path)?;
+
+    if lines_per_file == 0 {
+        return Err("Lines per file must be greater than zero".to_string());
+    }
+
+    let file_path = Path::new(&path);
+    let bytes = fs::read(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let encoding = file_encoding(&bytes);
+    let bom = detect_bom(&bytes);
+    let (content, _, _) = encoding.decode(&bytes);
+
+    let lines = split_lines_keep_endings(&content);
+    let chunks: Vec<_> = lines.chunks(lines_per_file).collect();
+    if chunks.len() < 2 {
+        return Err("File already fits within the requested chunk size".to_string());
+    }
+
+    let parent = file_path.parent()
+        .ok_or_else(|| "Invalid file path (no parent directory)".to_string())?;
+    let stem = file_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let ext = file_path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let width = chunks.len().to_string().len().max(3);
+
+    // Resolve and check every output name, and encode every chunk, before writing anything
+    let mut outputs = Vec::with_capacity(chunks.len());
+    for (index, chunk) in chunks.iter().enumerate() {
+        let name = format!("{}.part{:0width$}{}", stem, index + 1, ext, width = width);
+        let out_path = parent.join(name).to_string_lossy().to_string();
+        validate_write_path(&out_path)?;
+        if Path::new(&out_path).exists() {
+            return Err(format!("Output file already exists: {}", out_path));
+        }
+        let mut data = bom.to_vec();
+        data.extend(encode_content(&chunk.concat(), encoding)?);
+        outputs.push((out_path, data));
+    }
+
+    for (out_path, data) in &outputs {
+        fs::write(out_path, data).map_err(|e| format!("Failed to write {}: {}", out_path, e))?;
+    }
+
+    Ok(outputs.into_iter().map(|(out_path, _)| out_path).collect())
+}
+
+/// Concatenate the decoded content of several files into `output`, joined by
+/// `separator` (empty by default) and encoded as UTF-8 unless another label is given.
+#[tauri::command]
+fn merge_files(
+    paths: Vec<String>,
+    output: String,
+    separator: Option<String>,
+    encoding: Option<String>,
+) -> Result<(), String> {
+    if paths.is_empty() {
+        return Err("No files to merge".to_string());
+    }
+
+    // Validate every input and the output before reading anything
+    for path in &paths {
+        validate_file_path(path)?;
+    }
+    let (target, _) = resolve_save_target(&output)?;
+
+    let target_encoding = match encoding {
+        Some(label) => resolve_encoding(&label)?,
+        None => encoding_rs::UTF_8,
+    };
+
+    let mut parts = Vec::with_capacity(paths.len());
+    for path in &paths {
+        parts.push(read_text(Path::new(path))?);
+    }
+
+    let merged = parts.join(separator.as_deref().unwrap_or(""));
+    write_atomic(&target, &encode_content(&merged, target_encoding)?)
+        .map_err(|e| format!("Failed to write merged file: {}", e))
+}
+
+/// Probe whether an approved directory is actually writable by creating and
+/// removing a scratch file. Catches read-only mounts and ACL denials that
+/// `validate_write_path` cannot see.
+#[tauri::command]
+fn test_write_access(dir: String) -> Result<bool, String> {
+    validate_read_dir(&dir)?;
+
+    // A probe left behind by a crashed run must not read as "not writable"
+    for _ in 0..WRITE_PROBE_ATTEMPTS {
+        let probe = Path::new(&dir).join(format!(
+            ".crabtree-write-test-{}-{}",
+            std::process::id(),
+            NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let created = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe);
+
+        match created {
+            Ok(file) => {
+                drop(file);
+                fs::remove_file(&probe)
+                    .map_err(|e| format!("Write probe succeeded but cleanup failed: {}", e))?;
+                return Ok(true);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(_) => return Ok(false),
+        }
+    }
+    Err("Could not find an unused name for the write probe".to_string())
+}
+
+/// Probe names tried by `test_write_access` before giving up.
+const WRITE_PROBE_ATTEMPTS: usize = 16;
+
+/// Directories first, then case-insensitive by name.
+fn sort_dir_entries(items: &mut [fs::DirEntry]) {
+    items.sort_by(|a, b| {
+        let a_is_dir = a.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        let b_is_dir = b.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        b_is_dir.cmp(&a_is_dir).then(
+            a.file_name()
+                .to_string_lossy()
+                .to_lowercase()
+                .cmp(&b.file_name().to_string_lossy().to_lowercase()),
+        )
+    });
+}
+
+#[cfg(unix)]
+fn create_symlink(source: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(source, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(source: &Path, link: &Path) -> std::io::Result<()> {
+    if source.is_dir() {
+        std::os::windows::fs::symlink_dir(source, link)
+    } else {
+        std::os::windows::fs::symlink_file(source, link)
+    }
+}
+
+/// Create a hard link or symlink at `link` pointing to the approved `source`.
+#[tauri::command]
+fn create_link(source: String, link: String, symbolic: bool) -> Result<(), String> {
+    let canonical_source = is_path_allowed(&source)?;
+    validate_write_path(&link)?;
+
+    let link_path = Path::new(&link);
+    if link_path.symlink_metadata().is_ok() {
+        return Err(format!("Link path already exists: {}", link));
+    }
+
+    if symbolic {
+        create_symlink(&canonical_source, link_path).map_err(|e| {
+            // ERROR_PRIVILEGE_NOT_HELD: symlinks need admin rights or Developer Mode on Windows
+            if cfg!(windows) && e.raw_os_error() == Some(1314) {
+                "Creating symlinks on Windows requires administrator rights or Developer Mode".to_string()
+            } else {
+                format!("Failed to create symlink: {}", e)
+            }
+        })
+    } else {
+        if canonical_source.is_dir() {
+            return Err("Hard links to directories are not supported".to_string());
+        }
+        fs::hard_link(&canonical_source, link_path)
+            .map_err(|e| format!("Failed to create hard link: {}", e))
+    }
+}
+
+/// Copy `from` (a file or a whole directory tree) to `to`, then remove `from`.
+/// Used when `fs::rename` cannot move across filesystems.
+fn copy_then_remove(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(from)?.is_dir() {
+        for entry in walkdir::WalkDir::new(from) {
+            let entry = entry.map_err(std::io::Error::other)?;
+            let relative = entry.path().strip_prefix(from).map_err(std::io::Error::other)?;
+            let target = to.join(relative);
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&target)?;
+            } else {
+                fs::copy(entry.path(), &target)?;
+            }
+        }
+        fs::remove_dir_all(from)
+    } else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    }
+}
+
+/// Rename or move an approved file or directory. The destination's parent must be
+/// approved too, and an existing destination is only replaced when `overwrite`
+/// is set. Errors carry a `code` of `source_not_approved`,
+/// `destination_not_approved`, or `destination_exists` for the cases the UI
+/// handles differently.
+#[tauri::command]
+fn rename_path(from: String, to: String, overwrite: bool) -> Result<(), CommandError> {
+    let canonical_from = is_path_allowed(&from)
+        .map_err(|e| CommandError::new("source_not_approved", e))?;
+    validate_write_path(&to)
+        .map_err(|e| CommandError::new("destination_not_approved", e))?;
+
+    let to_path = Path::new(&to);
+    if to_path.symlink_metadata().is_ok() {
+        // Renaming onto itself (e.g. a case-only rename on a case-insensitive disk) is not a clash
+        let same = fs::canonicalize(to_path).is_ok_and(|t| t == canonical_from);
+        if !same && !overwrite {
+            return Err(CommandError::new(
+                "destination_exists",
+                format!("Destination already exists: {}", to),
+            ));
+        }
+    }
+
+    match fs::rename(&from, to_path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => copy_then_remove(Path::new(&from), to_path)
+            .map_err(|e| format!("Failed to move across devices: {}", e))?,
+        Err(e) => return Err(format!("Failed to rename: {}", e).into()),
+    }
+
+    invalidate_dir_caches_containing(&canonical_from);
+    if let Ok(canonical_to) = fs::canonicalize(to_path) {
+        invalidate_dir_caches_containing(&canonical_to);
+    }
+    Ok(())
+}
+
+/// Delete an approved file or directory. It goes to the OS trash / recycle bin
+/// unless `also_permanent` is set, in which case it is removed outright.
+#[tauri::command]
+fn delete_path(path: String, also_permanent: bool) -> Result<(), String> {
+    let canonical = is_path_allowed(&path)?;
+
+    // Act on the path itself so deleting a symlink removes the link, not its target
+    let target = Path::new(&path);
+    if also_permanent {
+        let is_dir = fs::symlink_metadata(target)
+            .map_err(|e| format!("Cannot access path: {}", e))?
+            .is_dir();
+        let result = if is_dir { fs::remove_dir_all(target) } else { fs::remove_file(target) };
+        result.map_err(|e| format!("Failed to delete: {}", e))?;
+    } else {
+        trash::delete(target).map_err(|e| format!("Failed to move to trash: {}", e))?;
+    }
+
+    // Prefetch watchers catch this too, but only after the debounce window
+    invalidate_dir_caches_containing(&canonical);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_identity(path: &Path) -> Result<String, String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path)
+        .map_err(|e| format!("Cannot access file metadata: {}", e))?;
+    Ok(format!("{:x}-{:x}", metadata.dev(), metadata.ino()))
+}
+
+#[cfg(windows)]
+fn file_identity(path: &Path) -> Result<String, String> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION,
+    };
+
+    let file = fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    // SAFETY: the handle is owned by `file` and stays open for the duration of the call,
+    // and `info` is a plain-old-data out parameter.
+    let info = unsafe {
+        let mut info: BY_HANDLE_FILE_INFORMATION = std::mem::zeroed();
+        if GetFileInformationByHandle(file.as_raw_handle(), &mut info) == 0 {
+            return Err(format!(
+                "Failed to query file identity: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        info
+    };
+
+    let index = ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64;
+    Ok(format!("{:x}-{:x}", info.dwVolumeSerialNumber, index))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn file_identity(_path: &Path) -> Result<String, String> {
+    Err("File ids are not supported on this platform".to_string())
+}
+
+/// Identities of files replaced by `write_atomic`, mapped to the id the file had
+/// before its first replacement this session.
+static FILE_ID_ALIASES: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+/// Record that the file known as `previous` now has identity `current`.
+fn carry_file_id(previous: String, current: String) {
+    if let Ok(mut aliases) = FILE_ID_ALIASES.lock() {
+        // The previous identity no longer exists on disk, so its entry can go
+        let original = aliases.remove(&previous).unwrap_or(previous);
+        if original != current {
+            aliases.insert(current, original);
+        }
+    }
+}
+
+/// Return an id for a file that survives renames and moves, built from the
+/// device + inode (Unix) or volume serial + file index (Windows). The id is only
+/// stable within one filesystem; moving across volumes yields a new one. Saves
+/// made by this app replace the file, and keep reporting the id it had before;
+/// a replacement by another program (as many editors save) yields a new id.
+#[tauri::command]
+fn get_file_id(path: String) -> Result<String, String> {
+    validate_file_path(&path)?;
+    let id = file_identity(Path::new(&path))?;
+    let mut aliases = FILE_ID_ALIASES.lock()
+        .map_err(|_| "File id lock poisoned".to_string())?;
+    if let Some(original) = aliases.get(&id) {
+        return Ok(original.clone());
+    }
+    // The inode a save freed can be reused by another file; never hand out one id twice
+    if aliases.values().any(|original| original == &id) {
+        let fresh = format!("{}-{}", id, NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed));
+        aliases.insert(id, fresh.clone());
+        return Ok(fresh);
+    }
+    Ok(id)
+}
+
+/// Milliseconds since the Unix epoch; times before it clamp to zero.
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub size: u64,
+    /// Last modification time in Unix milliseconds.
+    pub modified: u64,
+    /// Creation time in Unix milliseconds, where the platform and filesystem record it.
+    pub created: Option<u64>,
+    pub is_readonly: bool,
+    pub is_dir: bool,
+}
+
+/// Size, timestamps, and read-only state of an approved file or directory,
+/// without reading its contents.
+#[tauri::command]
+fn get_file_metadata(path: String) -> Result<FileMetadata, String> {
+    let canonical = is_path_allowed(&path)?;
+    let metadata = fs::metadata(&canonical)
+        .map_err(|e| format!("Failed to get metadata: {}", e))?;
+    let modified = metadata.modified()
+        .map_err(|e| format!("Modification time unavailable: {}", e))?;
+
+    Ok(FileMetadata {
+        size: metadata.len(),
+        modified: unix_millis(modified),
+        created: metadata.created().ok().map(unix_millis),
+        is_readonly: metadata.permissions().readonly(),
+        is_dir: metadata.is_dir(),
+    })
+}
+
+/// Bytes sampled from the start of a file when deciding whether it is binary.
+const BINARY_SAMPLE_BYTES: u64 = 8192;
+
+/// Read at most `limit` bytes from the start of a file.
+fn read_head(path: &Path, limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    fs::File::open(path)?.take(limit).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Share of control bytes above which a sample that is not valid UTF-8 counts as binary.
+const BINARY_CONTROL_RATIO: f64 = 0.1;
+
+/// Treat a sample as binary if it contains NUL bytes, unless a UTF-16 BOM explains
+/// them, or if it is not valid UTF-8 and dense with control bytes no text encoding
+/// uses. Legacy encodings are invalid UTF-8 too, so invalidity alone is not enough.
+fn is_binary_sample(sample: &[u8]) -> bool {
+    let bom = detect_bom(sample);
+    if bom == [0xFF, 0xFE] || bom == [0xFE, 0xFF] {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+
+    // The sample may end mid-character; only an error before the cut is invalid
+    let invalid = match std::str::from_utf8(sample) {
+        Ok(_) => false,
+        Err(e) => e.error_len().is_some(),
+    };
+    if !invalid {
+        return false;
+    }
+    let controls = sample
+        .iter()
+        .filter(|&&b| matches!(b, 0x01..=0x08 | 0x0E..=0x1A | 0x1C..=0x1F | 0x7F))
+        .count();
+    controls as f64 > sample.len() as f64 * BINARY_CONTROL_RATIO
+}
+
+/// Sample the head of a file to guess whether it is binary. Unreadable files are
+/// not treated as binary so they stay visible (and fail loudly when opened).
+fn looks_binary(path: &Path) -> bool {
+    read_head(path, BINARY_SAMPLE_BYTES)
+        .map(|head| is_binary_sample(&head))
+        .unwrap_or(false)
+}
+
+/// Bytes read from the start of a file when sniffing its language from content.
+const LANGUAGE_SNIFF_BYTES: u64 = 4096;
+
+/// Guess a language from the first bytes of a file. Returns None when nothing
+/// distinctive is found so the caller can fall back to the extension.
+fn sniff_language(sample: &str) -> Option<&'static str> {
+    let trimmed = sample.trim_start_matches('\u{FEFF}').trim_start();
+    let lower = trimmed.chars().take(256).collect::<String>().to_lowercase();
+
+    if lower.starts_with("<?xml") {
+        return Some("xml");
+    }
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return Some("html");
+    }
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        // A sample of a large file is truncated, so accept a leading key or value
+        // as evidence rather than requiring the whole sample to parse
+        let after = trimmed[1..].trim_start();
+        if serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+            || after.starts_with('"')
+            || after.starts_with(['}', ']', '{', '['])
+        {
+            return Some("json");
+        }
+    }
+    if trimmed.starts_with("---\n") || trimmed.starts_with("---\r\n") || trimmed.starts_with("%YAML") {
+        return Some("yaml");
+    }
+    None
+}
+
+/// Longest first line read when looking for a shebang.
+const SHEBANG_MAX_BYTES: u64 = 256;
+
+/// Read up to the first newline (or `limit` bytes) without touching the rest.
+fn read_first_line(path: &Path, limit: u64) -> std::io::Result<Vec<u8>> {
+    let file = fs::File::open(path)?;
+    let mut line = Vec::new();
+    std::io::BufReader::new(file.take(limit)).read_until(b'\n', &mut line)?;
+    Ok(line)
+}
+
+/// Map a `#!` line to a language, looking through `/usr/bin/env` and version
+/// suffixes (`python3.11`).
+fn shebang_language(line: &str) -> Option<&'static str> {
+    let rest = line.trim_start_matches('\u{FEFF}').strip_prefix("#!")?;
+    let mut words = rest.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // Skip `env` flags such as -S or -i
+        program = words.find(|w| !w.starts_with('-'))?;
+    }
+    let interpreter = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+    let lang = match interpreter {
+        "python" => "python",
+        "bash" | "sh" | "zsh" | "dash" | "ksh" => "shell",
+        "node" | "nodejs" => "javascript",
+        "ruby" => "ruby",
+        "perl" => "perl",
+        "lua" => "lua",
+        "php" => "php",
+        "pwsh" => "powershell",
+        _ => return None,
+    };
+    Some(lang)
+}
+
+/// Detect a file's language. By default the extension wins and content is only
+/// inspected when it is unrecognized: first a shebang on the first line, then
+/// sniffing of the head. With `trust_extension: false` content is always
+/// inspected first, for misnamed files like a `.txt` that is really JSON.
+#[tauri::command]
+fn detect_language(path: String, trust_extension: Option<bool>) -> Result<String, String> {
+    validate_file_path(&path)?;
+
+    let file_path = Path::new(&path);
+    let file_name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let by_extension = get_file_language(file_name);
+
+    if trust_extension.unwrap_or(true) && by_extension != "plaintext" {
+        return Ok(by_extension);
+    }
+
+    let first_line = read_first_line(file_path, SHEBANG_MAX_BYTES)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    if let Some(lang) = shebang_language(&String::from_utf8_lossy(&first_line)) {
+        return Ok(lang.to_string());
+    }
+
+    let head = read_head(file_path, LANGUAGE_SNIFF_BYTES)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    let (sample, _, _) = detect_encoding(&head).decode(&head);
+
+    Ok(sniff_language(&sample)
+        .map(str::to_string)
+        .unwrap_or(by_extension))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BinaryProbe {
+    pub size: u64,
+    pub mime_type: Option<String>,
+    pub extension: Option<String>,
+    /// Broad category from the magic bytes: "image", "archive", "audio", etc.
+    pub kind: Option<String>,
+    pub known_format: bool,
+    pub is_binary: bool,
+}
+
+/// Sniff a file's magic bytes so the UI can choose between an image preview,
+/// a hex view, or a "can't open" message.
+#[tauri::command]
+fn probe_binary(path: String) -> Result<BinaryProbe, String> {
+    validate_file_path(&path)?;
+
+    let file_path = Path::new(&path);
+    let metadata = fs::metadata(file_path).map_err(|e| format!("Failed to get metadata: {}", e))?;
+    let head = read_head(file_path, BINARY_SAMPLE_BYTES)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    let detected = infer::get(&head);
+    let kind = detected.map(|t| {
+        match t.matcher_type() {
+            infer::MatcherType::App => "application",
+            infer::MatcherType::Archive => "archive",
+            infer::MatcherType::Audio => "audio",
+            infer::MatcherType::Book => "book",
+            infer::MatcherType::Doc => "document",
+            infer::MatcherType::Font => "font",
+            infer::MatcherType::Image => "image",
+            infer::MatcherType::Text => "text",
+            infer::MatcherType::Video => "video",
+            infer::MatcherType::Custom => "custom",
+        }
+        .to_string()
+    });
+
+    Ok(BinaryProbe {
+        size: metadata.len(),
+        mime_type: detected.map(|t| t.mime_type().to_string()),
+        extension: detected.map(|t| t.extension().to_string()),
+        kind,
+        known_format: detected.is_some(),
+        is_binary: is_binary_sample(&head),
+    })
+}
+
+/// Entries the tree skips by default, whatever the other filters say, unless
+/// `show_always_skipped` is set.
+const ALWAYS_SKIPPED: &[&str] = &[".git", "node_modules", "target"];
+
+/// Why the tree builder hides an entry by name, if it does: the always-skipped
+/// directories, plus names starting with '.' when `hide_hidden` is set.
+fn skip_reason(name: &str, options: &TreeOptions) -> Option<&'static str> {
+    if !options.show_always_skipped && ALWAYS_SKIPPED.contains(&name) {
+        Some("'.git', 'node_modules' and 'target' are always skipped")
+    } else if options.hide_hidden && name.starts_with('.') {
+        Some("hidden entries (names starting with '.') are not shown")
+    } else {
+        None
+    }
+}
+
+/// Deepest `max_depth` a listing may ask for.
+const MAX_LIST_DEPTH: u32 = 32;
+
+/// Per-call tree settings accepted by `list_directory` and `expand_directory`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ListOptions {
+    /// Levels of subdirectories to fill in below the listed folder. 0 (the
+    /// default) lists only the folder's own children.
+    pub max_depth: Option<u32>,
+    /// Show names starting with '.'. Defaults to showing them when `.gitignore`
+    /// is respected and hiding them otherwise.
+    pub show_hidden: Option<bool>,
+    /// Glob patterns matched against entry names, skipped on top of the
+    /// built-in rules (e.g. `dist`, `*.min.js`).
+    pub extra_ignores: Vec<String>,
+    /// List `.git`, `node_modules` and `target` too, for projects where those
+    /// names hold real content.
+    pub show_always_skipped: bool,
+}
+
+/// Filters applied while building a file tree.
+#[derive(Clone)]
+struct TreeOptions {
+    /// Deepest level whose directories get their children filled in.
+    max_depth: u32,
+    /// Drop files that look binary.
+    text_only: bool,
+    /// Skip entries matched by `.gitignore` files in the tree.
+    respect_gitignore: bool,
+    /// Skip names starting with '.'.
+    hide_hidden: bool,
+    /// Caller-supplied name patterns to skip.
+    extra_ignores: Option<globset::GlobSet>,
+    /// Don't skip the `ALWAYS_SKIPPED` names.
+    show_always_skipped: bool,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        TreeOptions {
+            max_depth: 0,
+            text_only: false,
+            respect_gitignore: true,
+            hide_hidden: false,
+            extra_ignores: None,
+            show_always_skipped: false,
+        }
+    }
+}
+
+impl TreeOptions {
+    /// True for the settings the prefetch cache is built with.
+    fn is_default(&self) -> bool {
+        self.max_depth == 0
+            && !self.text_only
+            && self.respect_gitignore
+            && !self.hide_hidden
+            && self.extra_ignores.is_none()
+            && !self.show_always_skipped
+    }
+}
+
+/// Parse `dir/.gitignore`, if there is one. Malformed lines are dropped the way
+/// git drops them rather than failing the whole listing.
+fn load_gitignore(dir: &Path) -> Option<ignore::gitignore::Gitignore> {
+    let gitignore_path = dir.join(".gitignore");
+    if !gitignore_path.is_file() {
+        return None;
+    }
+    let mut builder = ignore::gitignore::GitignoreBuilder::new(dir);
+    builder.add(&gitignore_path);
+    builder.build().ok()
+}
+
+/// True when the deepest `.gitignore` with an opinion on `path` ignores it.
+fn gitignored(stack: &[ignore::gitignore::Gitignore], path: &Path, is_dir: bool) -> bool {
+    for matcher in stack.iter().rev() {
+        match matcher.matched(path, is_dir) {
+            ignore::Match::Ignore(_) => return true,
+            ignore::Match::Whitelist(_) => return false,
+            ignore::Match::None => {}
+        }
+    }
+    false
+}
+
+/// An entry the tree builder could not read, reported instead of silently
+/// showing an empty or missing folder.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ListingError {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DirectoryListing {
+    pub entries: Vec<FileEntry>,
+    pub errors: Vec<ListingError>,
+}
+
+/// State threaded through one tree walk.
+struct TreeWalk {
+    gitignores: Vec<ignore::gitignore::Gitignore>,
+    /// Canonical paths of the directories being walked, to catch symlink cycles.
+    ancestors: Vec<PathBuf>,
+    /// Approved roots, so followed symlinks are not walked outside them.
+    roots: Vec<PathBuf>,
+    errors: Vec<ListingError>,
+}
+
+impl TreeWalk {
+    fn error(&mut self, path: &Path, reason: String) {
+        self.errors.push(ListingError { path: path.to_string_lossy().to_string(), reason });
+    }
+}
+
+/// Build the tree under `dir` (which must already be validated), collecting
+/// unreadable entries alongside the ones that could be listed.
+fn list_tree(
+    dir: &Path,
+    options: &TreeOptions,
+    gitignores: Vec<ignore::gitignore::Gitignore>,
+) -> DirectoryListing {
+    let mut walk = TreeWalk {
+        gitignores,
+        ancestors: fs::canonicalize(dir).into_iter().collect(),
+        roots: current_approved_roots().unwrap_or_default(),
+        errors: Vec::new(),
+    };
+    let entries = build_file_tree(dir, 0, options, &mut walk);
+    DirectoryListing { entries, errors: walk.errors }
+}
+
+/// A directory entry that passed the tree's filters.
+struct KeptEntry {
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+    is_symlink: bool,
+}
+
+/// Apply the tree's name, extra-ignore, `.gitignore` and `text_only` filters to
+/// one entry of a directory whose `.gitignore` is already on `gitignores`.
+/// Shared by every command that walks a folder, so they all hide the same things.
+fn filter_tree_entry(
+    item: &fs::DirEntry,
+    options: &TreeOptions,
+    gitignores: &[ignore::gitignore::Gitignore],
+) -> Result<Option<KeptEntry>, String> {
+    let name = item.file_name().to_string_lossy().to_string();
+
+    if skip_reason(&name, options).is_some() {
+        return Ok(None);
+    }
+    if options.extra_ignores.as_ref().is_some_and(|set| set.is_match(&name)) {
+        return Ok(None);
+    }
+
+    let path = item.path();
+    let file_type = item.file_type().map_err(|e| format!("Failed to read file type: {}", e))?;
+    // Symlinked folders are listed as folders; dangling links stay plain entries
+    let is_symlink = file_type.is_symlink();
+    let is_dir = file_type.is_dir() || (is_symlink && fs::metadata(&path).is_ok_and(|m| m.is_dir()));
+
+    if options.respect_gitignore && gitignored(gitignores, &path, is_dir) {
+        return Ok(None);
+    }
+
+    // Directories are always kept; only files are sampled
+    if options.text_only && !is_dir && looks_binary(&path) {
+        return Ok(None);
+    }
+
+    Ok(Some(KeptEntry { name, path, is_dir, is_symlink }))
+}
+
+fn build_file_tree(dir: &Path, depth: u32, options: &TreeOptions, walk: &mut TreeWalk) -> Vec<FileEntry> {
+    if depth > options.max_depth {
+        return vec![];
+    }
+
+    let mut entries: Vec<FileEntry> = Vec::new();
+
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            walk.error(dir, format!("Failed to read directory: {}", e));
+            return entries;
+        }
+    };
+    let mut items = Vec::new();
+    for item in read_dir {
+        match item {
+            Ok(item) => items.push(item),
+            Err(e) => walk.error(dir, format!("Failed to read entry: {}", e)),
+        }
+    }
+    sort_dir_entries(&mut items);
+
+    let pushed = match options.respect_gitignore.then(|| load_gitignore(dir)).flatten() {
+        Some(matcher) => {
+            walk.gitignores.push(matcher);
+            true
+        }
+        None => false,
+    };
+
+    for item in items {
+        let kept = match filter_tree_entry(&item, options, &walk.gitignores) {
+            Ok(Some(kept)) => kept,
+            Ok(None) => continue,
+            Err(e) => {
+                walk.error(&item.path(), e);
+                continue;
+            }
+        };
+
+        let children = if kept.is_dir && depth < options.max_depth {
+            descend(&kept.path, kept.is_symlink, depth, options, walk)
+        } else {
+            None
+        };
+
+        entries.push(FileEntry {
+            name: kept.name,
+            path: kept.path.to_string_lossy().to_string(),
+            is_dir: kept.is_dir,
+            children,
+        });
+    }
+
+    if pushed {
+        walk.gitignores.pop();
+    }
+
+    entries
+}
+
+/// Children of a subdirectory, or None (with an error recorded) when it loops
+/// back to a folder being walked or is a symlink leading out of approved roots.
+fn descend(
+    path: &Path,
+    is_symlink: bool,
+    depth: u32,
+    options: &TreeOptions,
+    walk: &mut TreeWalk,
+) -> Option<Vec<FileEntry>> {
+    let canonical = match fs::canonicalize(path) {
+        Ok(canonical) => canonical,
+        Err(e) => {
+            walk.error(path, format!("Cannot resolve path: {}", e));
+            return None;
+        }
+    };
+    if walk.ancestors.contains(&canonical) {
+        walk.error(path, format!("Symlink cycle: points back to {}", canonical.display()));
+        return None;
+    }
+    if is_symlink && !path_under_any(&canonical, &walk.roots) {
+        walk.error(path, format!("Symlink leads outside the approved folders to {}", canonical.display()));
+        return None;
+    }
+
+    walk.ancestors.push(canonical);
+    let children = build_file_tree(path, depth + 1, options, walk);
+    walk.ancestors.pop();
+    Some(children)
+}
+
+fn tree_options(
+    text_only: Option<bool>,
+    respect_gitignore: Option<bool>,
+    hide_hidden: Option<bool>,
+    options: Option<ListOptions>,
+) -> Result<TreeOptions, String> {
+    let options = options.unwrap_or_default();
+    let max_depth = options.max_depth.unwrap_or(0);
+    if max_depth > MAX_LIST_DEPTH {
+        return Err(format!("max_depth must be at most {}", MAX_LIST_DEPTH));
+    }
+    let extra_ignores = if options.extra_ignores.is_empty() {
+        None
+    } else {
+        Some(build_globset(&options.extra_ignores)?)
+    };
+
+    let respect_gitignore = respect_gitignore.unwrap_or(true);
+    let hide_hidden = match options.show_hidden {
+        Some(show) => !show,
+        None => hide_hidden.unwrap_or(!respect_gitignore),
+    };
+    Ok(TreeOptions {
+        max_depth,
+        text_only: text_only.unwrap_or(false),
+        respect_gitignore,
+        hide_hidden,
+        extra_ignores,
+        show_always_skipped: options.show_always_skipped,
+    })
+}
+
+/// `.gitignore` matchers from the approved folders above `dir`, outermost
+/// first, so a lazily expanded subfolder is filtered as if walked from the root.
+fn ancestor_gitignores(dir: &Path) -> Vec<ignore::gitignore::Gitignore> {
+    let allowed = match APPROVED_PATHS.lock() {
+        Ok(allowed) => allowed.clone(),
+        Err(_) => return Vec::new(),
+    };
+
+    let mut stack = Vec::new();
+    for ancestor in dir.ancestors().skip(1) {
+        let within = fs::canonicalize(ancestor).is_ok_and(|c| path_under_any(&c, &allowed));
+        if !within {
+            break;
+        }
+        stack.extend(load_gitignore(ancestor));
+    }
+    stack.reverse();
+    stack
+}
+
+/// List the immediate children of an approved directory, directories first.
+/// Subdirectories come back with `children: None`; call `expand_directory` when
+/// one is opened. By default entries matched by `.gitignore` files are skipped
+/// and hidden files are shown; pass `respect_gitignore: false` for the fixed
+/// rules (hidden files, `node_modules`, `target`) used before `.gitignore`
+/// support, or `hide_hidden` to choose hidden-file filtering explicitly.
+/// `options` sets the depth, hidden-file visibility (overriding `hide_hidden`),
+/// and extra name patterns to skip.
+#[tauri::command]
+fn list_directory(
+    path: String,
+    text_only: Option<bool>,
+    respect_gitignore: Option<bool>,
+    hide_hidden: Option<bool>,
+    options: Option<ListOptions>,
+) -> Result<DirectoryListing, String> {
+    // Check allowlist first (security)
+    validate_read_dir(&path)?;
+
+    let options = tree_options(text_only, respect_gitignore, hide_hidden, options)?;
+
+    // The prefetch cache only holds trees built with the default options
+    if options.is_default() {
+        if let Some(listing) = cached_tree(&path) {
+            return Ok(listing);
+        }
+    }
+
+    Ok(list_tree(Path::new(&path), &options, Vec::new()))
+}
+
+/// List the children of a folder the user opened in the tree, with the same
+/// filters as `list_directory`, including `.gitignore` files above it. Served
+/// from the prefetch cache when the default filters are used.
+#[tauri::command]
+fn expand_directory(
+    path: String,
+    text_only: Option<bool>,
+    respect_gitignore: Option<bool>,
+    hide_hidden: Option<bool>,
+    options: Option<ListOptions>,
+) -> Result<DirectoryListing, String> {
+    validate_read_dir(&path)?;
+
+    let options = tree_options(text_only, respect_gitignore, hide_hidden, options)?;
+    if options.is_default() {
+        if let Some(listing) = cached_tree(&path) {
+            return Ok(listing);
+        }
+    }
+
+    let dir_path = Path::new(&path);
+    let gitignores = if options.respect_gitignore {
+        ancestor_gitignores(dir_path)
+    } else {
+        Vec::new()
+    };
+    Ok(list_tree(dir_path, &options, gitignores))
+}
+
+#[derive(Serialize, Clone)]
+struct DirEntryEvent {
+    dir: String,
+    entry: FileEntry,
+}
+
+#[derive(Serialize, Clone)]
+struct DirCompleteEvent {
+    dir: String,
+    count: usize,
+    /// Entry paths in tree order (directories first, then by name) so the UI
+    /// can re-sort what it rendered progressively.
+    sorted_paths: Vec<String>,
+    error: Option<String>,
+}
+
+/// Stream the immediate children of an approved directory as `dir-entry` events
+/// while they are read, then send `dir-complete`. Entries arrive unsorted; the
+/// completion event carries the final order. Takes the same filters as
+/// `list_directory`; `max_depth` is ignored since only one level is streamed.
+#[tauri::command]
+fn list_directory_streaming(
+    app: AppHandle,
+    path: String,
+    text_only: Option<bool>,
+    respect_gitignore: Option<bool>,
+    hide_hidden: Option<bool>,
+    options: Option<ListOptions>,
+) -> Result<(), String> {
+    validate_read_dir(&path)?;
+    let options = tree_options(text_only, respect_gitignore, hide_hidden, options)?;
+    let read_dir = fs::read_dir(&path)
+        .map_err(|e| format!("Failed to read directory: {}", e))?;
+
+    let mut gitignores = Vec::new();
+    if options.respect_gitignore {
+        gitignores = ancestor_gitignores(Path::new(&path));
+        gitignores.extend(load_gitignore(Path::new(&path)));
+    }
+
+    std::thread::spawn(move || {
+        let mut seen: Vec<FileEntry> = Vec::new();
+        let mut error = None;
+
+        for item in read_dir {
+            let item = match item {
+                Ok(item) => item,
+                Err(e) => {
+                    error = Some(format!("Failed to read entry: {}", e));
+                    continue;
+                }
+            };
+            let kept = match filter_tree_entry(&item, &options, &gitignores) {
+                Ok(Some(kept)) => kept,
+                Ok(None) => continue,
+                Err(e) => {
+                    error = Some(e);
+                    continue;
+                }
+            };
+
+            let entry = FileEntry {
+                name: kept.name,
+                path: kept.path.to_string_lossy().to_string(),
+                is_dir: kept.is_dir,
+                children: None,
+            };
+            let payload = DirEntryEvent { dir: path.clone(), entry: entry.clone() };
+            if let Err(e) = app.emit("dir-entry", payload) {
+                eprintln!("Warning: failed to emit dir-entry event: {}", e);
+            }
+            seen.push(entry);
+        }
+
+        seen.sort_by(|a, b| {
+            b.is_dir
+                .cmp(&a.is_dir)
+                .then(a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+        let payload = DirCompleteEvent {
+            dir: path,
+            count: seen.len(),
+            sorted_paths: seen.into_iter().map(|e| e.path).collect(),
+            error,
+        };
+        if let Err(e) = app.emit("dir-complete", payload) {
+            eprintln!("Warning: failed to emit dir-complete event: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+// ─── Directory Cache ───
+/// A prefetched one-level listing plus the folder mtime it was built against.
+struct CachedTree {
+    modified: Option<SystemTime>,
+    listing: DirectoryListing,
+}
+
+/// Prefetched listings keyed by the path string the frontend passes to
+/// `list_directory` (for the root) or `expand_directory` (for subfolders).
+static DIR_CACHE: Lazy<Mutex<HashMap<String, CachedTree>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+/// Bumped on every invalidation so a walk that raced with a change never
+/// publishes a stale tree.
+static DIR_CACHE_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn dir_modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Return a cached listing if the folder mtime still matches what it was built against.
+fn cached_tree(path: &str) -> Option<DirectoryListing> {
+    let cache = DIR_CACHE.lock().ok()?;
+    let cached = cache.get(path)?;
+    (cached.modified == dir_modified(path)).then(|| cached.listing.clone())
+}
+
+/// Drop the cached listings of `path` and every folder under it.
+fn invalidate_dir_cache(path: &str) {
+    DIR_CACHE_GENERATION.fetch_add(1, Ordering::SeqCst);
+    if let Ok(mut cache) = DIR_CACHE.lock() {
+        cache.retain(|key, _| !Path::new(key).starts_with(path));
+    }
+}
+
+/// Drop every cached listing of a folder that contains `changed` or lies under
+/// it, for edits made by this process that must show up in the very next listing.
+fn invalidate_dir_caches_containing(changed: &Path) {
+    DIR_CACHE_GENERATION.fetch_add(1, Ordering::SeqCst);
+    if let Ok(mut cache) = DIR_CACHE.lock() {
+        cache.retain(|key, _| {
+            let Ok(dir) = fs::canonicalize(key) else {
+                return false;
+            };
+            !changed.starts_with(&dir) && !dir.starts_with(changed)
+        });
+    }
+}
+
+/// Levels of subfolders below the prefetched folder whose listings are cached.
+const PREFETCH_MAX_DEPTH: u32 = 3;
+
+/// Cap on the folders one prefetch lists, so a huge tree doesn't stall the walk.
+const PREFETCH_MAX_DIRS: usize = 2000;
+
+/// List `dir` as `expand_directory` would and queue it for the cache, then do
+/// the same for its subfolders down to `depth_left` more levels.
+fn prefetch_tree(
+    dir: &Path,
+    mut gitignores: Vec<ignore::gitignore::Gitignore>,
+    depth_left: u32,
+    out: &mut Vec<(String, CachedTree)>,
+) {
+    if out.len() >= PREFETCH_MAX_DIRS {
+        return;
+    }
+
+    let key = dir.to_string_lossy().to_string();
+    let modified = dir_modified(&key);
+    let listing = list_tree(dir, &TreeOptions::default(), gitignores.clone());
+    let subdirs: Vec<PathBuf> = listing
+        .entries
+        .iter()
+        .filter(|entry| entry.is_dir)
+        .map(|entry| PathBuf::from(&entry.path))
+        .collect();
+    out.push((key, CachedTree { modified, listing }));
+
+    if depth_left == 0 {
+        return;
+    }
+    if let Some(matcher) = load_gitignore(dir) {
+        gitignores.push(matcher);
+    }
+    for subdir in subdirs {
+        prefetch_tree(&subdir, gitignores.clone(), depth_left - 1, out);
+    }
+}
+
+/// Walk an approved directory in the background and cache each folder's listing
+/// so the next `list_directory` on it, and `expand_directory` on the folders
+/// beneath, return instantly. A watcher drops the cached listings on any change
+/// underneath, since a folder's mtime alone misses edits to its files.
+#[tauri::command]
+fn prefetch_directory(path: String) -> Result<(), String> {
+    let canonical = is_path_allowed(&path)?;
+    validate_read_dir(&path)?;
+
+    let watch_id = format!("prefetch:{}", path);
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+    if let Entry::Vacant(slot) = watchers.entry(watch_id) {
+        let cache_key = path.clone();
+        let mut debouncer = new_debouncer(
+            Duration::from_millis(WATCH_DEBOUNCE_MS),
+            move |_: DebounceEventResult| invalidate_dir_cache(&cache_key),
+        )
+        .map_err(|e| format!("Failed to create watcher: {}", e))?;
+        debouncer
+            .watcher()
+            .watch(&canonical, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", canonical.display(), e))?;
+        slot.insert(RegisteredWatcher::new(vec![canonical], debouncer));
+    }
+    drop(watchers);
+
+    std::thread::spawn(move || {
+        let generation = DIR_CACHE_GENERATION.load(Ordering::SeqCst);
+        let root = Path::new(&path);
+        let mut listings = Vec::new();
+        prefetch_tree(root, ancestor_gitignores(root), PREFETCH_MAX_DEPTH, &mut listings);
+
+        if let Ok(mut cache) = DIR_CACHE.lock() {
+            // A revoke that landed mid-walk must not be undone by publishing it
+            if DIR_CACHE_GENERATION.load(Ordering::SeqCst) == generation && is_path_allowed(&path).is_ok() {
+                cache.extend(listings);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// List immediate children whose name starts with `prefix` (case-insensitive),
+/// for type-ahead jumps within one folder without building the subtree. Hides
+/// the same entries as the tree, and reports the ones it could not read.
+#[tauri::command]
+fn list_directory_prefix(path: String, prefix: String) -> Result<DirectoryListing, String> {
+    validate_read_dir(&path)?;
+
+    let dir = Path::new(&path);
+    let options = TreeOptions::default();
+    let mut gitignores = ancestor_gitignores(dir);
+    gitignores.extend(load_gitignore(dir));
+
+    let needle = prefix.to_lowercase();
+    let read_dir = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read directory: {}", e))?;
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for item in read_dir {
+        let item = match item {
+            Ok(item) => item,
+            Err(e) => {
+                errors.push(ListingError { path: path.clone(), reason: format!("Failed to read entry: {}", e) });
+                continue;
+            }
+        };
+        if !item.file_name().to_string_lossy().to_lowercase().starts_with(&needle) {
+            continue;
+        }
+        match filter_tree_entry(&item, &options, &gitignores) {
+            Ok(Some(kept)) => entries.push(FileEntry {
+                name: kept.name,
+                path: kept.path.to_string_lossy().to_string(),
+                is_dir: kept.is_dir,
+                children: None,
+            }),
+            Ok(None) => {}
+            Err(reason) => errors.push(ListingError { path: item.path().to_string_lossy().to_string(), reason }),
+        }
+    }
+
+    entries.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then(a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+    Ok(DirectoryListing { entries, errors })
+}
+
+// ─── Language Overrides ───
+/// User-editable `extension -> language` map, stored in the app config dir.
+const LANGUAGE_MAP_FILE: &str = "language-map.json";
+
+/// Registry key for the language map watcher in `WATCHERS`.
+const LANGUAGE_MAP_WATCH_ID: &str = "language-map";
+
+/// Active extension overrides consulted by `get_file_language` before the built-in table.
+static LANGUAGE_OVERRIDES: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+fn language_map_path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join(LANGUAGE_MAP_FILE))
+        .map_err(|e| format!("Cannot resolve config directory: {}", e))
+}
+
+/// Parse the override JSON, normalizing keys so `.RS`, `rs`, and ` rs ` all match.
+fn parse_language_map(raw: &str) -> Result<HashMap<String, String>, String> {
+    let parsed: HashMap<String, String> = serde_json::from_str(raw)
+        .map_err(|e| format!("Invalid language map: {}", e))?;
+
+    Ok(parsed
+        .into_iter()
+        .map(|(ext, lang)| (ext.trim().trim_start_matches('.').to_lowercase(), lang.trim().to_string()))
+        .filter(|(ext, lang)| !ext.is_empty() && !lang.is_empty())
+        .collect())
+}
+
+/// Reload overrides from disk. A missing file clears them; a malformed file
+/// is rejected and the previous map stays in effect.
+fn reload_language_map(path: &Path) -> Result<(), String> {
+    let map = match fs::read_to_string(path) {
+        Ok(raw) => parse_language_map(&raw)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => return Err(format!("Failed to read language map: {}", e)),
+    };
+
+    let mut overrides = LANGUAGE_OVERRIDES.lock()
+        .map_err(|_| "Language map lock poisoned".to_string())?;
+    *overrides = map;
+    Ok(())
+}
+
+/// Watch the language map file and reload it live, emitting
+/// `language-map-reloaded` so the UI can re-highlight open files.
+#[tauri::command]
+fn watch_language_map(app: AppHandle) -> Result<(), String> {
+    let map_path = language_map_path(&app)?;
+    let config_dir = map_path.parent()
+        .ok_or_else(|| "Invalid language map path".to_string())?
+        .to_path_buf();
+    fs::create_dir_all(&config_dir)
+        .map_err(|e| format!("Failed to create config directory: {}", e))?;
+
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+    if watchers.contains_key(LANGUAGE_MAP_WATCH_ID) {
+        return Ok(());
+    }
+
+    // Watch the directory rather than the file: editors often save by replacing it
+    let watched_file = map_path.clone();
+    let mut debouncer = new_debouncer(
+        Duration::from_millis(WATCH_DEBOUNCE_MS),
+        move |result: DebounceEventResult| {
+            let touched = match result {
+                Ok(events) => events.iter().any(|event| event.path == watched_file),
+                Err(e) => {
+                    eprintln!("Warning: language map watcher error: {}", e);
+                    false
+                }
+            };
+            if !touched {
+                return;
+            }
+            match reload_language_map(&watched_file) {
+                Ok(()) => {
+                    if let Err(e) = app.emit("language-map-reloaded", ()) {
+                        eprintln!("Warning: failed to emit language-map-reloaded event: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("Warning: keeping previous language map: {}", e),
+            }
+        },
+    )
+    .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+    debouncer
+        .watcher()
+        .watch(&config_dir, RecursiveMode::NonRecursive)
+        .map_err(|e| format!("Failed to watch config directory: {}", e))?;
+
+    // The config dir is outside the allowlist, so no revocation should reach it
+    watchers.insert(LANGUAGE_MAP_WATCH_ID.to_string(), RegisteredWatcher::new(Vec::new(), debouncer));
+    Ok(())
+}
+
+// ─── Source File Enumeration ───
+/// Build a glob matcher from user-supplied patterns, rejecting invalid ones up front.
+fn build_globset(patterns: &[String]) -> Result<globset::GlobSet, String> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = globset::Glob::new(pattern)
+            .map_err(|e| format!("Invalid glob '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| format!("Invalid glob set: {}", e))
+}
+
+/// Most files `enumerate_source_files` collects, so a huge monorepo can't stall
+/// the finder or a reference scan.
+const SOURCE_FILES_LIMIT: usize = 50_000;
+
+/// Collect every file under `root` that survives `.gitignore`/hidden-file rules,
+/// plus any ignored file whose root-relative path matches `include_overrides`,
+/// stopping at `limit` files. Also returns whether the limit cut the walk short.
+fn enumerate_source_files(
+    root: &Path,
+    include_overrides: &[String],
+    limit: usize,
+) -> Result<(Vec<PathBuf>, bool), String> {
+    let mut files: Vec<PathBuf> = ignore::WalkBuilder::new(root)
+        .require_git(false)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.into_path())
+        .take(limit + 1)
+        .collect();
+
+    if !include_overrides.is_empty() {
+        // Overrides re-include on top of the normal walk, unlike `ignore`'s own
+        // overrides which would turn into a whitelist and hide everything else
+        let overrides = build_globset(include_overrides)?;
+        let reincluded = walkdir::WalkDir::new(root)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter(|entry| {
+                // A folder pattern such as `dist` re-includes everything beneath it
+                entry.path().strip_prefix(root).is_ok_and(|rel| {
+                    rel.ancestors()
+                        .filter(|prefix| !prefix.as_os_str().is_empty())
+                        .any(|prefix| overrides.is_match(prefix))
+                })
+            })
+            .map(|entry| entry.into_path())
+            .take(limit + 1);
+        files.extend(reincluded);
+    }
+
+    files.sort();
+    files.dedup();
+    let truncated = files.len() > limit;
+    files.truncate(limit);
+    Ok((files, truncated))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SymlinkEntry {
+    pub path: String,
+    /// Link target exactly as stored in the link.
+    pub target: String,
+    /// Fully resolved target, or None when the link is dangling.
+    pub resolved: Option<String>,
+    pub dangling: bool,
+    /// True when the resolved target lies outside the approved root.
+    pub escapes_root: bool,
+}
+
+/// Walk an approved tree and report every symlink with its target, flagging
+/// dangling links and links that resolve outside the root.
+#[tauri::command]
+fn list_symlinks(root: String) -> Result<Vec<SymlinkEntry>, String> {
+    validate_read_dir(&root)?;
+    let canonical_root = fs::canonicalize(&root)
+        .map_err(|e| format!("Cannot resolve path: {}", e))?;
+
+    let links = walkdir::WalkDir::new(&canonical_root)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path_is_symlink())
+        .map(|entry| {
+            let path = entry.path();
+            let target = fs::read_link(path)
+                .map(|t| t.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let resolved = fs::canonicalize(path).ok();
+            let escapes_root = resolved
+                .as_ref()
+                .is_some_and(|r| !path_under_any(r, std::slice::from_ref(&canonical_root)));
+
+            SymlinkEntry {
+                path: path.to_string_lossy().to_string(),
+                target,
+                dangling: resolved.is_none(),
+                resolved: resolved.map(|r| r.to_string_lossy().to_string()),
+                escapes_root,
+            }
+        })
+        .collect();
+
+    Ok(links)
+}
+
+/// Files larger than this are skipped when scanning for references.
+const REFERENCE_SCAN_MAX_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Find source files under `root` that appear to use `target`: they mention its
+/// file name, or a relative import path to it (with or without extension). A
+/// text heuristic rather than a parser, meant for "where is this used" before a
+/// rename or delete.
+#[tauri::command]
+fn find_references(root: String, target: String) -> Result<Vec<String>, String> {
+    validate_read_dir(&root)?;
+    let target_path = is_path_allowed(&target)?;
+    let canonical_root = fs::canonicalize(&root)
+        .map_err(|e| format!("Cannot resolve path: {}", e))?;
+
+    let file_name = target_path
+        .file_name()
+        .ok_or_else(|| "Invalid target path".to_string())?
+        .to_string_lossy()
+        .to_string();
+    let target_no_ext = target_path.with_extension("");
+
+    let mut references = Vec::new();
+    let (files, _) = enumerate_source_files(&canonical_root, &[], SOURCE_FILES_LIMIT)?;
+    for file in files {
+        if file == target_path {
+            continue;
+        }
+        let too_big = fs::metadata(&file).map(|m| m.len() > REFERENCE_SCAN_MAX_BYTES).unwrap_or(true);
+        if too_big || looks_binary(&file) {
+            continue;
+        }
+        let Ok(content) = read_text(&file) else {
+            continue;
+        };
+
+        let mut needles = vec![file_name.clone()];
+        if let Some(dir) = file.parent() {
+            for candidate in [&target_path, &target_no_ext] {
+                if let Some(rel) = relative_path(dir, candidate) {
+                    needles.push(import_path_form(&rel));
+                }
+            }
+        }
+
+        if needles.iter().any(|needle| content.contains(needle.as_str())) {
+            references.push(file.to_string_lossy().to_string());
+        }
+    }
+
+    Ok(references)
+}
+
+/// Upper bound on matches returned by `search_in_directory`.
+const SEARCH_MAX_RESULTS: usize = 5000;
+/// Files larger than this are skipped when searching.
+const SEARCH_MAX_FILE_BYTES: u64 = 5 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchMatch {
+    pub path: String,
+    /// 1-based line number.
+    pub line_number: usize,
+    pub line: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchResults {
+    pub matches: Vec<SearchMatch>,
+    /// True when the search stopped at `SEARCH_MAX_RESULTS`.
+    pub truncated: bool,
+}
+
+/// Collect the file paths of a tree in listing order.
+fn tree_files(entries: Vec<FileEntry>, out: &mut Vec<PathBuf>) {
+    for entry in entries {
+        match entry.children {
+            Some(children) => tree_files(children, out),
+            None if !entry.is_dir => out.push(PathBuf::from(entry.path)),
+            None => {}
+        }
+    }
+}
+
+/// Find lines containing `query` in every text file under an approved directory,
+/// skipping the same entries as the file tree (with its default filters) and
+/// anything that looks binary.
+#[tauri::command]
+fn search_in_directory(path: String, query: String, case_sensitive: bool) -> Result<SearchResults, String> {
+    validate_read_dir(&path)?;
+    if query.is_empty() {
+        return Err("Search query must not be empty".to_string());
+    }
+    let needle = if case_sensitive { query } else { query.to_lowercase() };
+
+    // Walk exactly as the tree would, including symlinked folders and cycle checks
+    let root = Path::new(&path);
+    let options = TreeOptions { max_depth: MAX_LIST_DEPTH, ..TreeOptions::default() };
+    let mut files = Vec::new();
+    tree_files(list_tree(root, &options, ancestor_gitignores(root)).entries, &mut files);
+
+    let mut matches = Vec::new();
+    for file in files {
+        let too_big = fs::metadata(&file).map(|m| m.len() > SEARCH_MAX_FILE_BYTES).unwrap_or(true);
+        if too_big {
+            continue;
+        }
+        let Ok(bytes) = fs::read(&file) else {
+            continue;
+        };
+        if is_binary_sample(&bytes[..bytes.len().min(BINARY_SAMPLE_BYTES as usize)]) {
+            continue;
+        }
+
+        let (content, _, _) = file_encoding(&bytes).decode(&bytes);
+        for (index, line) in split_lines_keep_endings(&content).into_iter().enumerate() {
+            let line = line.trim_end_matches(['\r', '\n']);
+            let found = if case_sensitive {
+                line.contains(needle.as_str())
+            } else {
+                line.to_lowercase().contains(needle.as_str())
+            };
+            if !found {
+                continue;
+            }
+            if matches.len() == SEARCH_MAX_RESULTS {
+                return Ok(SearchResults { matches, truncated: true });
+            }
+            matches.push(SearchMatch {
+                path: file.to_string_lossy().to_string(),
+                line_number: index + 1,
+                line: line.to_string(),
+            });
+        }
+    }
+
+    Ok(SearchResults { matches, truncated: false })
+}
+
+/// One sortable line per listed entry: directories by path, files by path,
+/// size and mtime.
+fn fingerprint_records(entries: Vec<FileEntry>, root: &Path, out: &mut Vec<String>) {
+    for entry in entries {
+        let path = PathBuf::from(&entry.path);
+        let rel = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().to_string();
+        if entry.is_dir {
+            out.push(format!("d\0{}", rel));
+            fingerprint_records(entry.children.unwrap_or_default(), root, out);
+            continue;
+        }
+        let (len, modified) = fs::metadata(&path)
+            .map(|m| {
+                let modified = m
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|d| d.as_nanos())
+                    .unwrap_or(0);
+                (m.len(), modified)
+            })
+            .unwrap_or((0, 0));
+        out.push(format!("f\0{}\0{}\0{}", rel, len, modified));
+    }
+}
+
+/// Hash the (path, size, mtime) of every entry the tree would show under an
+/// approved directory into one digest, so the UI can cheaply tell whether a
+/// re-list is needed. Walks with the tree's own filters, so an unchanged
+/// fingerprint means re-listing would show the same thing. Directories
+/// contribute only their path: their mtime also moves when hidden files change,
+/// which would defeat the point.
+#[tauri::command]
+fn directory_fingerprint(path: String) -> Result<String, String> {
+    validate_read_dir(&path)?;
+    let root = Path::new(&path);
+
+    let options = TreeOptions { max_depth: MAX_LIST_DEPTH, ..TreeOptions::default() };
+    let listing = list_tree(root, &options, ancestor_gitignores(root));
+    let mut records = Vec::new();
+    fingerprint_records(listing.entries, root, &mut records);
+    records.extend(listing.errors.into_iter().map(|e| format!("e\0{}\0{}", e.path, e.reason)));
+    records.sort();
+
+    let mut hasher = Sha256::new();
+    for record in &records {
+        hasher.update(record.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Explain why `path` is missing from the tree under `root`: the built-in skip
+/// rule or `.gitignore` pattern that excludes it, or None if nothing does.
+/// Deeper `.gitignore` files take precedence, as in git.
+#[tauri::command]
+fn explain_ignored(root: String, path: String) -> Result<Option<String>, String> {
+    validate_read_dir(&root)?;
+    let canonical_root = fs::canonicalize(&root)
+        .map_err(|e| format!("Cannot resolve path: {}", e))?;
+    let canonical = is_path_allowed(&path)?;
+
+    let rel = canonical
+        .strip_prefix(&canonical_root)
+        .map_err(|_| "Path is outside the given root".to_string())?;
+
+    // Built-in rules apply to every component, since skipping a folder hides its contents
+    let mut current = canonical_root.clone();
+    for component in rel.components() {
+        current.push(component);
+        let name = component.as_os_str().to_string_lossy();
+        if let Some(reason) = skip_reason(&name, &TreeOptions::default()) {
+            return Ok(Some(format!("'{}' is skipped: {}", current.display(), reason)));
+        }
+    }
+
+    let mut levels = vec![canonical_root.clone()];
+    if let Some(parent_rel) = rel.parent() {
+        let mut dir = canonical_root.clone();
+        for component in parent_rel.components() {
+            dir.push(component);
+            levels.push(dir.clone());
+        }
+    }
+
+    let is_dir = canonical.is_dir();
+    for dir in levels.iter().rev() {
+        let gitignore_path = dir.join(".gitignore");
+        if !gitignore_path.is_file() {
+            continue;
+        }
+        let mut builder = ignore::gitignore::GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(&gitignore_path) {
+            return Err(format!("Failed to parse {}: {}", gitignore_path.display(), e));
+        }
+        let matcher = builder.build()
+            .map_err(|e| format!("Failed to parse {}: {}", gitignore_path.display(), e))?;
+
+        match matcher.matched_path_or_any_parents(&canonical, is_dir) {
+            ignore::Match::Ignore(glob) => {
+                return Ok(Some(format!(
+                    "Matched pattern '{}' in {}",
+                    glob.original(),
+                    gitignore_path.display()
+                )));
+            }
+            ignore::Match::Whitelist(_) => return Ok(None),
+            ignore::Match::None => {}
+        }
+    }
+
+    Ok(None)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SourceFileList {
+    pub files: Vec<String>,
+    /// True when the tree held more than `SOURCE_FILES_LIMIT` files.
+    pub truncated: bool,
+}
+
+/// List source files under an approved root, honoring `.gitignore` except for
+/// paths matching `include_overrides` (e.g. `dist/**` when searching build output).
+/// Async and walked on a blocking thread, so a big tree never holds up the UI.
+#[tauri::command]
+async fn list_source_files(root: String, include_overrides: Option<Vec<String>>) -> Result<SourceFileList, String> {
+    validate_read_dir(&root)?;
+
+    let overrides = include_overrides.unwrap_or_default();
+    let (files, truncated) = tauri::async_runtime::spawn_blocking(move || {
+        enumerate_source_files(Path::new(&root), &overrides, SOURCE_FILES_LIMIT)
+    })
+    .await
+    .map_err(|e| format!("File listing failed: {}", e))??;
+
+    Ok(SourceFileList {
+        files: files.into_iter().map(|p| p.to_string_lossy().to_string()).collect(),
+        truncated,
+    })
+}
+
+/// Languages for files recognized by their whole name rather than an extension.
+fn special_file_language(name: &str) -> Option<&'static str> {
+    let lang = match name {
+        "dockerfile" | "containerfile" => "dockerfile",
+        "makefile" | "gnumakefile" | "cmakelists.txt" => "cmake",
+        "gemfile" | "rakefile" | "podfile" | "vagrantfile" | "brewfile" => "ruby",
+        "cargo.lock" | "pipfile" => "toml",
+        ".bashrc" | ".bash_profile" | ".zshrc" | ".profile" => "shell",
+        ".editorconfig" | ".gitconfig" => "ini",
+        ".gitignore" | ".gitattributes" | ".dockerignore" | ".npmignore" => "plaintext",
+        _ => return None,
+    };
+    Some(lang)
+}
+
+#[tauri::command]
+fn get_file_language(file_name: String) -> String {
+    let path = Path::new(&file_name);
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if let Some(lang) = special_file_language(&name) {
+        return lang.to_string();
+    }
+    // Variants such as `Dockerfile.dev` or `Makefile.linux` keep the base name's language
+    if let Some((base, _)) = name.split_once('.') {
+        if matches!(base, "dockerfile" | "containerfile" | "makefile") {
+            if let Some(lang) = special_file_language(base) {
+                return lang.to_string();
+            }
+        }
+    }
+
+    let ext = path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+
+    let override_lang = LANGUAGE_OVERRIDES
+        .lock()
+        .ok()
+        .and_then(|overrides| overrides.get(&ext).cloned());
+    if let Some(lang) = override_lang {
+        return lang;
+    }
+
+    match ext.as_str() {
+        "rs" => "rust",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" | "mts" | "cts" => "typescript",
+        "jsx" => "jsx",
+        "tsx" => "tsx",
+        "py" | "pyw" => "python",
+        "java" => "java",
+        "c" => "c",
+        "cpp" | "cc" | "cxx" | "c++" => "cpp",
+        "h" | "hpp" | "hxx" => "cpp",
+        "cs" => "csharp",
+        "go" => "go",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "kt" | "kts" => "kotlin",
         "scala" => "scala",
         "r" => "r",
         "html" | "htm" => "html",
@@ -340,6 +3371,240 @@ fn get_file_language(file_name: String) -> String {
     .to_string()
 }
 
+// ─── Filename Safety ───
+#[derive(Serialize, Deserialize)]
+pub struct FilenameSafety {
+    pub is_safe: bool,
+    pub has_control_chars: bool,
+    pub has_bidi_controls: bool,
+    pub has_invisible_chars: bool,
+    pub mixed_script: bool,
+    pub warnings: Vec<String>,
+}
+
+/// Bidirectional formatting characters that can visually reorder a name,
+/// e.g. making `invoice\u{202E}fdp.exe` render as `invoiceexe.pdf`.
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Zero-width characters that hide inside a name without rendering.
+fn is_invisible_char(c: char) -> bool {
+    matches!(c, '\u{00AD}' | '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}')
+}
+
+/// Cyrillic and Greek letters include many lookalikes of Latin ones (`а` vs `a`).
+fn is_confusable_script(c: char) -> bool {
+    matches!(c, '\u{0370}'..='\u{03FF}' | '\u{0400}'..='\u{052F}')
+}
+
+/// Flag filename tricks used to spoof extensions or impersonate other files:
+/// control characters, bidi overrides, invisible characters, and Latin mixed
+/// with lookalike scripts. Meant for warning before opening files from untrusted folders.
+#[tauri::command]
+fn is_safe_filename(name: String) -> Result<FilenameSafety, String> {
+    if name.is_empty() {
+        return Err("File name cannot be empty".to_string());
+    }
+
+    let has_control_chars = name.chars().any(char::is_control);
+    let has_bidi_controls = name.chars().any(is_bidi_control);
+    let has_invisible_chars = name.chars().any(is_invisible_char);
+    let mixed_script = name.chars().any(|c| c.is_ascii_alphabetic())
+        && name.chars().any(is_confusable_script);
+
+    let mut warnings = Vec::new();
+    if has_control_chars {
+        warnings.push("Name contains control characters (e.g. newline or NUL)".to_string());
+    }
+    if has_bidi_controls {
+        warnings.push("Name contains bidirectional override characters that can disguise its extension".to_string());
+    }
+    if has_invisible_chars {
+        warnings.push("Name contains invisible zero-width characters".to_string());
+    }
+    if mixed_script {
+        warnings.push("Name mixes Latin with lookalike Cyrillic or Greek letters".to_string());
+    }
+
+    Ok(FilenameSafety {
+        is_safe: warnings.is_empty(),
+        has_control_chars,
+        has_bidi_controls,
+        has_invisible_chars,
+        mixed_script,
+        warnings,
+    })
+}
+
+// ─── Generated/Vendored Detection ───
+/// Exact file names that are always machine-written (lockfiles and similar).
+const GENERATED_FILE_NAMES: &[&str] = &[
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "cargo.lock",
+    "composer.lock",
+    "gemfile.lock",
+    "poetry.lock",
+];
+
+/// File name suffixes produced by minifiers and code generators.
+const GENERATED_FILE_SUFFIXES: &[&str] = &[
+    ".min.js",
+    ".min.css",
+    ".map",
+    ".pb.go",
+    "_pb2.py",
+    ".pb.cc",
+    ".pb.h",
+    ".g.dart",
+    ".designer.cs",
+];
+
+/// Directory names whose contents are third-party code.
+const VENDORED_DIRS: &[&str] = &["vendor", "node_modules", "third_party", "bower_components"];
+
+/// Header markers used by generators to flag their output.
+const GENERATED_MARKERS: &[&str] = &["@generated", "code generated", "do not edit", "auto-generated"];
+
+/// How many leading lines to scan for a generated marker.
+const GENERATED_HEADER_LINES: usize = 5;
+
+fn has_generated_header(path: &Path) -> Result<bool, String> {
+    let head = read_head(path, 4096)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    let text = String::from_utf8_lossy(&head).to_lowercase();
+    Ok(text
+        .lines()
+        .take(GENERATED_HEADER_LINES)
+        .any(|line| GENERATED_MARKERS.iter().any(|marker| line.contains(marker))))
+}
+
+/// Heuristically decide whether a file is generated or vendored, so the UI can
+/// dim it in search and navigation results.
+#[tauri::command]
+fn is_generated_file(path: String) -> Result<bool, String> {
+    validate_file_path(&path)?;
+    let canonical = fs::canonicalize(&path)
+        .map_err(|e| format!("Cannot resolve path: {}", e))?;
+
+    let name = canonical
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+
+    if GENERATED_FILE_NAMES.contains(&name.as_str())
+        || GENERATED_FILE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+    {
+        return Ok(true);
+    }
+
+    // Only folders inside the project count: a checkout under `~/vendor/` is not vendored code
+    let roots = current_approved_roots()?;
+    let root = roots
+        .iter()
+        .filter(|root| canonical.starts_with(root))
+        .min_by_key(|root| root.components().count());
+    let vendored = match (root, canonical.parent()) {
+        (Some(root), Some(parent)) => parent
+            .strip_prefix(root)
+            .map(|rel| {
+                rel.components().any(|c| {
+                    VENDORED_DIRS.contains(&c.as_os_str().to_string_lossy().to_lowercase().as_str())
+                })
+            })
+            .unwrap_or(false),
+        _ => false,
+    };
+    if vendored {
+        return Ok(true);
+    }
+
+    has_generated_header(&canonical)
+}
+
+/// Resolve the directory an "open terminal here" action should start in: the
+/// path itself for a directory, its parent for a file. The result must itself be
+/// approved, matching what `run_task` accepts as a cwd.
+#[tauri::command]
+fn terminal_cwd_for(path: String) -> Result<String, String> {
+    let canonical = is_path_allowed(&path)?;
+    let dir = if canonical.is_dir() {
+        canonical
+    } else {
+        canonical.parent()
+            .ok_or_else(|| "Invalid file path (no parent directory)".to_string())?
+            .to_path_buf()
+    };
+
+    let dir_str = dir.to_string_lossy().to_string();
+    validate_read_dir(&dir_str)?;
+    Ok(dir_str)
+}
+
+/// Path from directory `base` to `target`, walking up with `..` past the
+/// common ancestor. Returns None when they share no root (different drives on Windows).
+fn relative_path(base: &Path, target: &Path) -> Option<PathBuf> {
+    let base_parts: Vec<_> = base.components().collect();
+    let target_parts: Vec<_> = target.components().collect();
+
+    let common = base_parts
+        .iter()
+        .zip(&target_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return None;
+    }
+
+    let mut rel = PathBuf::new();
+    for _ in common..base_parts.len() {
+        rel.push("..");
+    }
+    for part in &target_parts[common..] {
+        rel.push(part.as_os_str());
+    }
+    Some(rel)
+}
+
+/// Render a relative path the way an import statement spells it: forward
+/// slashes, and a leading `./` unless it already climbs with `..`.
+fn import_path_form(rel: &Path) -> String {
+    let rel = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if rel == ".." || rel.starts_with("../") {
+        rel
+    } else {
+        format!("./{}", rel)
+    }
+}
+
+/// Relative path from one file to another in import-statement form, e.g.
+/// `../utils/helper.ts` or `./sibling.ts`, always using forward slashes.
+#[tauri::command]
+fn relative_import_path(from: String, to: String) -> Result<String, String> {
+    let from_path = is_path_allowed(&from)?;
+    let to_path = is_path_allowed(&to)?;
+
+    let base = if from_path.is_dir() {
+        from_path.as_path()
+    } else {
+        from_path.parent()
+            .ok_or_else(|| "Invalid file path (no parent directory)".to_string())?
+    };
+
+    let rel = relative_path(base, &to_path)
+        .ok_or_else(|| "Files do not share a common root".to_string())?;
+    Ok(import_path_form(&rel))
+}
+
 #[derive(Serialize, Deserialize)]
 struct TaskRunResult {
     ok: bool,
@@ -360,56 +3625,318 @@ fn validate_task_command(command: &str) -> Result<(), String> {
 }
 
 #[tauri::command]
-fn run_task(
-    command: String,
-    args: Vec<String>,
-    cwd: Option<String>,
-    env: Option<HashMap<String, String>>,
-) -> Result<TaskRunResult, String> {
-    validate_task_command(&command)?;
+fn run_task(
+    command: String,
+    args: Vec<String>,
+    cwd: Option<String>,
+    env: Option<HashMap<String, String>>,
+) -> Result<TaskRunResult, String> {
+    validate_task_command(&command)?;
+
+    let mut cmd = Command::new(command.trim());
+    cmd.args(args);
+
+    if let Some(raw_cwd) = cwd {
+        if !raw_cwd.trim().is_empty() {
+            validate_read_dir(&raw_cwd)?;
+            let canonical = fs::canonicalize(&raw_cwd)
+                .map_err(|e| format!("Cannot resolve task cwd: {}", e))?;
+            cmd.current_dir(canonical);
+        }
+    }
+
+    if let Some(env_map) = env {
+        for (k, v) in env_map {
+            if k.len() > 64 {
+                eprintln!("Warning: env var name '{}' exceeds 64 chars, skipping", &k[..k.len().min(32)]);
+                continue;
+            }
+            if v.len() > 8192 {
+                eprintln!("Warning: env var '{}' value exceeds 8192 chars, skipping", k);
+                continue;
+            }
+            cmd.env(k, v);
+        }
+    }
+
+    let started = Instant::now();
+    let output = cmd
+        .output()
+        .map_err(|e| format!("Failed to run task: {}", e))?;
+    let elapsed = started.elapsed().as_millis();
+    let exit_code = output.status.code().unwrap_or(-1);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+    Ok(TaskRunResult {
+        ok: output.status.success(),
+        exit_code,
+        stdout,
+        stderr,
+        duration_ms: elapsed,
+    })
+}
+
+// ─── Git ───
+/// List files that differ between `base` and the working tree (staged, unstaged,
+/// and untracked), as absolute paths sorted for display.
+#[tauri::command]
+fn git_diff_files(repo_root: String, base: String) -> Result<Vec<String>, String> {
+    validate_read_dir(&repo_root)?;
+    let canonical_root = fs::canonicalize(&repo_root)
+        .map_err(|e| format!("Cannot resolve repository root: {}", e))?;
+
+    let repo = git2::Repository::open(&canonical_root)
+        .map_err(|e| format!("Not a git repository: {}", e))?;
+    let workdir = repo.workdir()
+        .ok_or_else(|| "Repository has no working tree".to_string())?
+        .to_path_buf();
+
+    let base_tree = repo
+        .revparse_single(&base)
+        .and_then(|obj| obj.peel_to_tree())
+        .map_err(|e| format!("Cannot resolve base '{}': {}", base, e))?;
+
+    let mut opts = git2::DiffOptions::new();
+    opts.include_untracked(true).recurse_untracked_dirs(true);
+
+    let diff = repo
+        .diff_tree_to_workdir_with_index(Some(&base_tree), Some(&mut opts))
+        .map_err(|e| format!("Failed to diff against '{}': {}", base, e))?;
+
+    let mut files: Vec<String> = diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(|rel| workdir.join(rel).to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    files.dedup();
+
+    Ok(files)
+}
+
+// ─── File Watching ───
+/// Debounce window for watcher events. Most editors and formatters write a
+/// file several times per save, so bursts are collapsed into one notification.
+const WATCH_DEBOUNCE_MS: u64 = 250;
+
+/// A running watcher plus the canonical paths it covers, so revoking a folder
+/// can find every watcher beneath it.
+struct RegisteredWatcher {
+    roots: Vec<PathBuf>,
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+impl RegisteredWatcher {
+    fn new(roots: Vec<PathBuf>, debouncer: Debouncer<RecommendedWatcher>) -> Self {
+        RegisteredWatcher { roots, _debouncer: debouncer }
+    }
+}
+
+/// Active watchers keyed by handle id. Dropping a debouncer stops its
+/// watcher thread, so removing an entry is all the teardown needed.
+static WATCHERS: Lazy<Mutex<HashMap<String, RegisteredWatcher>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+static NEXT_WATCH_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Serialize, Clone)]
+struct FileChangedEvent {
+    handle: String,
+    path: String,
+}
+
+/// Start a debouncer that emits `file-changed` under `handle` for every change
+/// beneath `targets`.
+fn spawn_change_watcher(
+    app: AppHandle,
+    handle: String,
+    targets: &[(PathBuf, RecursiveMode)],
+) -> Result<Debouncer<RecommendedWatcher>, String> {
+    let mut debouncer = new_debouncer(
+        Duration::from_millis(WATCH_DEBOUNCE_MS),
+        move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                for event in events {
+                    let payload = FileChangedEvent {
+                        handle: handle.clone(),
+                        path: event.path.to_string_lossy().to_string(),
+                    };
+                    if let Err(e) = app.emit("file-changed", payload) {
+                        eprintln!("Warning: failed to emit file-changed event: {}", e);
+                    }
+                }
+            }
+            Err(e) => eprintln!("Warning: watcher error on {}: {}", handle, e),
+        },
+    )
+    .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+    for (target, mode) in targets {
+        debouncer
+            .watcher()
+            .watch(target, *mode)
+            .map_err(|e| format!("Failed to watch {}: {}", target.display(), e))?;
+    }
+    Ok(debouncer)
+}
+
+fn watch_mode(path: &Path) -> RecursiveMode {
+    if path.is_dir() {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    }
+}
+
+/// Watch several approved paths under a single debounced watcher.
+/// Returns a handle id that `unwatch_handle` uses to stop all of them at once.
+#[tauri::command]
+fn watch_paths(app: AppHandle, paths: Vec<String>) -> Result<String, String> {
+    if paths.is_empty() {
+        return Err("No paths to watch".to_string());
+    }
+
+    // Validate everything up front so a single bad path doesn't leave a half-registered watcher
+    let mut targets = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let canonical = is_path_allowed(path)?;
+        let mode = watch_mode(&canonical);
+        targets.push((canonical, mode));
+    }
+
+    let id = format!("watch-{}", NEXT_WATCH_ID.fetch_add(1, Ordering::Relaxed));
+    let debouncer = spawn_change_watcher(app, id.clone(), &targets)?;
+    let roots = targets.into_iter().map(|(target, _)| target).collect();
+
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+    watchers.insert(id.clone(), RegisteredWatcher::new(roots, debouncer));
+
+    Ok(id)
+}
+
+/// Registry key for a single-path watcher, so `unwatch_path` can find it by path.
+fn path_watch_id(canonical: &Path) -> String {
+    format!("path:{}", canonical.display())
+}
+
+/// Watch one approved file or directory (recursively) and emit `file-changed`
+/// whenever something under it is modified, created, or removed. Watching a
+/// path that is already watched is a no-op.
+#[tauri::command]
+fn watch_path(app: AppHandle, path: String) -> Result<(), String> {
+    let canonical = is_path_allowed(&path)?;
+    let id = path_watch_id(&canonical);
+
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+    if let Entry::Vacant(slot) = watchers.entry(id.clone()) {
+        let mode = watch_mode(&canonical);
+        let debouncer = spawn_change_watcher(app, id, &[(canonical.clone(), mode)])?;
+        slot.insert(RegisteredWatcher::new(vec![canonical], debouncer));
+    }
+    Ok(())
+}
+
+/// Stop a watcher started with `watch_path`.
+#[tauri::command]
+fn unwatch_path(path: String) -> Result<(), String> {
+    // Resolve the same way watch_path did; fall back to the raw path if it has since been removed
+    let canonical = fs::canonicalize(&path).unwrap_or_else(|_| PathBuf::from(&path));
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+
+    watchers
+        .remove(&path_watch_id(&canonical))
+        .map(|_| ())
+        .ok_or_else(|| format!("Path is not being watched: {}", path))
+}
+
+#[derive(Serialize, Clone)]
+struct NewFileMatchedEvent {
+    handle: String,
+    path: String,
+}
+
+/// Watch an approved directory and emit `new-file-matched` whenever a file whose
+/// name matches the glob `pattern` appears (created or moved in), e.g. to follow
+/// rotating logs. Files present when watching starts are not reported. Returns a
+/// handle for `unwatch_handle`.
+#[tauri::command]
+fn watch_for_new_files(app: AppHandle, dir: String, pattern: String) -> Result<String, String> {
+    validate_read_dir(&dir)?;
+    let canonical = fs::canonicalize(&dir)
+        .map_err(|e| format!("Cannot resolve path: {}", e))?;
+    let matcher = globset::Glob::new(&pattern)
+        .map_err(|e| format!("Invalid glob '{}': {}", pattern, e))?
+        .compile_matcher();
 
-    let mut cmd = Command::new(command.trim());
-    cmd.args(args);
+    let mut known: HashSet<PathBuf> = fs::read_dir(&canonical)
+        .map_err(|e| format!("Failed to read directory: {}", e))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect();
 
-    if let Some(raw_cwd) = cwd {
-        if !raw_cwd.trim().is_empty() {
-            validate_read_dir(&raw_cwd)?;
-            let canonical = fs::canonicalize(&raw_cwd)
-                .map_err(|e| format!("Cannot resolve task cwd: {}", e))?;
-            cmd.current_dir(canonical);
-        }
-    }
+    let id = format!("watch-{}", NEXT_WATCH_ID.fetch_add(1, Ordering::Relaxed));
+    let handle = id.clone();
 
-    if let Some(env_map) = env {
-        for (k, v) in env_map {
-            if k.len() > 64 {
-                eprintln!("Warning: env var name '{}' exceeds 64 chars, skipping", &k[..k.len().min(32)]);
-                continue;
-            }
-            if v.len() > 8192 {
-                eprintln!("Warning: env var '{}' value exceeds 8192 chars, skipping", k);
-                continue;
+    // The debouncer reports "something happened at path" without a kind, so new
+    // files are recognised by comparing against what has already been seen
+    let mut debouncer = new_debouncer(
+        Duration::from_millis(WATCH_DEBOUNCE_MS),
+        move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                for event in events {
+                    // Forget removed files so a rotated log recreated under the same name counts as new
+                    if !event.path.exists() {
+                        known.remove(&event.path);
+                        continue;
+                    }
+                    if !event.path.is_file() || !known.insert(event.path.clone()) {
+                        continue;
+                    }
+                    let matches = event.path.file_name().is_some_and(|name| matcher.is_match(name));
+                    if !matches {
+                        continue;
+                    }
+                    let payload = NewFileMatchedEvent {
+                        handle: handle.clone(),
+                        path: event.path.to_string_lossy().to_string(),
+                    };
+                    if let Err(e) = app.emit("new-file-matched", payload) {
+                        eprintln!("Warning: failed to emit new-file-matched event: {}", e);
+                    }
+                }
             }
-            cmd.env(k, v);
-        }
-    }
+            Err(e) => eprintln!("Warning: watcher error on {}: {}", handle, e),
+        },
+    )
+    .map_err(|e| format!("Failed to create watcher: {}", e))?;
 
-    let started = Instant::now();
-    let output = cmd
-        .output()
-        .map_err(|e| format!("Failed to run task: {}", e))?;
-    let elapsed = started.elapsed().as_millis();
-    let exit_code = output.status.code().unwrap_or(-1);
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    debouncer
+        .watcher()
+        .watch(&canonical, RecursiveMode::NonRecursive)
+        .map_err(|e| format!("Failed to watch {}: {}", canonical.display(), e))?;
 
-    Ok(TaskRunResult {
-        ok: output.status.success(),
-        exit_code,
-        stdout,
-        stderr,
-        duration_ms: elapsed,
-    })
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+    watchers.insert(id.clone(), RegisteredWatcher::new(vec![canonical], debouncer));
+
+    Ok(id)
+}
+
+/// Stop every path registered under a watcher handle.
+#[tauri::command]
+fn unwatch_handle(id: String) -> Result<(), String> {
+    let mut watchers = WATCHERS.lock()
+        .map_err(|_| "Watcher registry lock poisoned".to_string())?;
+
+    watchers
+        .remove(&id)
+        .map(|_| ())
+        .ok_or_else(|| format!("Unknown watcher handle: {}", id))
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -417,17 +3944,1065 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
+        .setup(|app| {
+            match app.path().app_config_dir() {
+                Ok(dir) => {
+                    if let Err(e) = load_allowlist(dir.join(ALLOWLIST_FILE)) {
+                        eprintln!("Warning: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("Warning: Cannot resolve config directory: {}", e),
+            }
+            match language_map_path(app.handle()) {
+                Ok(path) => {
+                    if let Err(e) = reload_language_map(&path) {
+                        eprintln!("Warning: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("Warning: {}", e),
+            }
+            if let Err(e) = watch_language_map(app.handle().clone()) {
+                eprintln!("Warning: language map will not reload live: {}", e);
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             approve_path,
             approve_path_within,
+            remap_paths,
+            revoke_path,
             clear_approved_paths,
+            force_session_encoding,
+            analyze_content,
             read_file,
+            set_read_size_limit,
+            text_stats,
+            check_encoding_declaration,
+            parse_env_file,
+            format_json,
+            minify_json,
+            validate_format,
+            read_file_delta,
+            estimate_file_complexity,
             save_file,
             save_file_as,
+            save_would_change,
+            save_file_unique,
+            create_and_open,
+            create_file,
+            create_directory,
+            split_file,
+            merge_files,
+            test_write_access,
+            create_link,
+            rename_path,
+            delete_path,
+            get_file_id,
+            get_file_metadata,
+            probe_binary,
             list_directory,
+            expand_directory,
+            list_directory_prefix,
+            list_directory_streaming,
+            prefetch_directory,
+            list_symlinks,
+            find_references,
+            search_in_directory,
+            directory_fingerprint,
+            list_source_files,
+            explain_ignored,
+            watch_language_map,
             get_file_language,
-            run_task
+            detect_language,
+            is_safe_filename,
+            is_generated_file,
+            terminal_cwd_for,
+            relative_import_path,
+            run_task,
+            git_diff_files,
+            watch_paths,
+            watch_path,
+            unwatch_path,
+            watch_for_new_files,
+            unwatch_handle
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn path_under_any_accepts_exact_match() {
+        assert!(path_under_any(Path::new("/foo"), &roots(&["/foo"])));
+    }
+
+    #[test]
+    fn path_under_any_accepts_nested_child() {
+        let approved = roots(&["/foo"]);
+        assert!(path_under_any(Path::new("/foo/bar"), &approved));
+        assert!(path_under_any(Path::new("/foo/bar/baz.txt"), &approved));
+    }
+
+    #[test]
+    fn path_under_any_rejects_sibling_sharing_prefix() {
+        let approved = roots(&["/foo"]);
+        assert!(!path_under_any(Path::new("/foobar"), &approved));
+        assert!(!path_under_any(Path::new("/foobar/baz.txt"), &approved));
+        assert!(!path_under_any(Path::new("/fo"), &approved));
+    }
+
+    #[test]
+    fn path_under_any_ignores_trailing_slashes() {
+        assert!(path_under_any(Path::new("/foo/bar"), &roots(&["/foo/"])));
+        assert!(path_under_any(Path::new("/foo/"), &roots(&["/foo"])));
+        assert!(!path_under_any(Path::new("/foobar/"), &roots(&["/foo/"])));
+    }
+
+    #[test]
+    fn path_under_any_rejects_parent_of_root() {
+        assert!(!path_under_any(Path::new("/"), &roots(&["/foo"])));
+        assert!(!path_under_any(Path::new("/foo"), &roots(&["/foo/bar"])));
+    }
+
+    #[test]
+    fn path_under_any_checks_every_root() {
+        let approved = roots(&["/alpha", "/beta/gamma"]);
+        assert!(path_under_any(Path::new("/beta/gamma/file.rs"), &approved));
+        assert!(!path_under_any(Path::new("/beta/other"), &approved));
+    }
+
+    #[test]
+    fn path_under_any_rejects_empty_allowlist() {
+        assert!(!path_under_any(Path::new("/foo"), &[]));
+    }
+
+    fn path_str(path: &Path) -> String {
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn save_file_rejects_unapproved_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("notes.txt");
+        fs::write(&target, "original").unwrap();
+
+        let err = save_file(path_str(&target), "overwritten".to_string(), None, None, None, None, None).unwrap_err();
+        assert!(err.message.starts_with("Access denied"), "unexpected error: {}", err.message);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "original");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn merge_files_rejects_output_symlink_escaping_approved_folder() {
+        let approved = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let victim = outside.path().join("victim.txt");
+        fs::write(&victim, "original").unwrap();
+        fs::write(approved.path().join("a.txt"), "pwn").unwrap();
+        fs::write(approved.path().join("b.txt"), "ed").unwrap();
+        let link = approved.path().join("merged.txt");
+        std::os::unix::fs::symlink(&victim, &link).unwrap();
+        approve_path(path_str(approved.path())).unwrap();
+
+        let inputs = vec![path_str(&approved.path().join("a.txt")), path_str(&approved.path().join("b.txt"))];
+        let err = merge_files(inputs.clone(), path_str(&link), None, None).unwrap_err();
+        assert!(err.starts_with("Access denied"), "unexpected error: {}", err);
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "original");
+
+        let output = approved.path().join("out.txt");
+        merge_files(inputs, path_str(&output), Some("-".to_string()), None).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "pwn-ed");
+    }
+
+    #[test]
+    fn save_file_writes_previously_approved_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("notes.txt");
+        fs::write(&target, "original").unwrap();
+        approve_path(path_str(&target)).unwrap();
+
+        save_file(path_str(&target), "updated".to_string(), None, None, None, None, None).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "updated");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_file_rejects_symlink_escaping_approved_folder() {
+        let approved = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("secret.txt");
+        fs::write(&secret, "secret").unwrap();
+        let link = approved.path().join("link.txt");
+        std::os::unix::fs::symlink(&secret, &link).unwrap();
+        approve_path(path_str(approved.path())).unwrap();
+
+        let err = save_file(path_str(&link), "pwned".to_string(), None, None, None, None, None).unwrap_err();
+        assert!(err.message.starts_with("Access denied"), "unexpected error: {}", err.message);
+        assert_eq!(fs::read_to_string(&secret).unwrap(), "secret");
+        let probe = save_would_change(path_str(&link), "secret".to_string(), "UTF-8".to_string(), "LF".to_string(), None);
+        assert!(probe.unwrap_err().starts_with("Access denied"));
+    }
+
+    #[test]
+    fn save_file_round_trips_shift_jis() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("legacy.txt");
+        let text = "日本語のテキストファイルです。\n文字コードはシフトJISで保存されています。\n";
+        let (original, _, _) = encoding_rs::SHIFT_JIS.encode(text);
+        fs::write(&target, &original).unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let file = read_file(path_str(&target), None, None, None, None).unwrap();
+        assert_eq!(file.encoding, "Shift_JIS");
+        save_file(path_str(&target), file.content, Some(file.encoding), None, None, None, None).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), original.as_ref());
+    }
+
+    #[test]
+    fn save_file_rejects_unknown_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("notes.txt");
+        fs::write(&target, "original").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let err = save_file(path_str(&target), "updated".to_string(), Some("klingon".to_string()), None, None, None, None).unwrap_err();
+        assert_eq!(err.message, "Unknown encoding: klingon");
+        assert_eq!(fs::read_to_string(&target).unwrap(), "original");
+    }
+
+    #[test]
+    fn save_file_rejects_unmappable_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("legacy.txt");
+        fs::write(&target, "original").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let err = save_file(path_str(&target), "絵文字 😀".to_string(), Some("Shift_JIS".to_string()), None, None, None, None).unwrap_err();
+        assert!(err.message.contains("U+1F600"), "unexpected error: {}", err.message);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "original");
+    }
+
+    #[test]
+    fn save_would_change_accounts_for_byte_order_marks() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("bom.txt");
+        fs::write(&target, b"\xEF\xBB\xBFhello\r\n").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let file = read_file(path_str(&target), None, None, None, None).unwrap();
+        let unchanged = save_would_change(path_str(&target), file.content.clone(), file.encoding.clone(), file.line_ending.clone(), Some(file.has_bom));
+        assert!(!unchanged.unwrap());
+        let dropped_bom = save_would_change(path_str(&target), file.content, file.encoding, file.line_ending, None);
+        assert!(dropped_bom.unwrap());
+    }
+
+    #[test]
+    fn save_file_keeps_crlf_line_endings() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("windows.txt");
+        fs::write(&target, "one\r\ntwo\r\nthree\r\n").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let file = read_file(path_str(&target), None, None, None, None).unwrap();
+        assert_eq!(file.line_ending, "CRLF");
+        save_file(path_str(&target), file.content, None, Some(file.line_ending), None, None, None).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"one\r\ntwo\r\nthree\r\n");
+    }
+
+    #[test]
+    fn save_file_normalizes_mixed_line_endings() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("mixed.txt");
+        approve_path(path_str(dir.path())).unwrap();
+
+        save_file(path_str(&target), "a\r\nb\nc\rd".to_string(), None, Some("CRLF".to_string()), None, None, None).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"a\r\nb\r\nc\r\nd");
+
+        let err = save_file(path_str(&target), "x".to_string(), None, Some("NEL".to_string()), None, None, None).unwrap_err();
+        assert_eq!(err.message, "Unknown line ending: NEL");
+    }
+
+    #[test]
+    fn save_file_restores_byte_order_marks() {
+        let dir = tempfile::tempdir().unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let utf16le: Vec<u8> = [0xFF, 0xFE].into_iter()
+            .chain("hi\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+            .collect();
+        let fixtures: [(&str, &[u8]); 3] = [
+            ("utf8.txt", b"\xEF\xBB\xBFhello\r\n"),
+            ("utf16le.txt", &utf16le),
+            ("plain.txt", b"no bom\n"),
+        ];
+        for (name, original) in fixtures {
+            let target = dir.path().join(name);
+            fs::write(&target, original).unwrap();
+
+            let file = read_file(path_str(&target), None, None, None, None).unwrap();
+            assert!(!file.content.starts_with('\u{FEFF}'), "{} kept the BOM in content", name);
+            save_file(path_str(&target), file.content, Some(file.encoding), Some(file.line_ending), Some(file.has_bom), None, None).unwrap();
+            assert_eq!(fs::read(&target).unwrap(), original, "{} changed on save", name);
+        }
+    }
+
+    #[test]
+    fn search_in_directory_decodes_text_and_skips_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let (latin1, _, _) = encoding_rs::WINDOWS_1252.encode("première ligne\nCafé au lait\n");
+        fs::write(dir.path().join("menu.txt"), &latin1).unwrap();
+        fs::write(dir.path().join("blob.bin"), b"cafe\0\0\0").unwrap();
+        fs::create_dir(dir.path().join("node_modules")).unwrap();
+        fs::write(dir.path().join("node_modules").join("dep.js"), "café").unwrap();
+        // Same rules as the tree: gitignored files are skipped, dotfiles are not
+        fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+        fs::write(dir.path().join("debug.log"), "café").unwrap();
+        fs::write(dir.path().join(".notes"), "café crème").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let results = search_in_directory(path_str(dir.path()), "CAFÉ AU".to_string(), false).unwrap();
+        assert!(!results.truncated);
+        assert_eq!(results.matches.len(), 1);
+        assert!(results.matches[0].path.ends_with("menu.txt"));
+        assert_eq!(results.matches[0].line_number, 2);
+        assert_eq!(results.matches[0].line, "Café au lait");
+
+        let results = search_in_directory(path_str(dir.path()), "café".to_string(), true).unwrap();
+        let paths: Vec<_> = results.matches.iter().map(|m| Path::new(&m.path).file_name().unwrap().to_owned()).collect();
+        assert_eq!(paths, [".notes"]);
+
+        let results = search_in_directory(path_str(dir.path()), "CAFÉ".to_string(), true).unwrap();
+        assert!(results.matches.is_empty());
+    }
+
+    #[test]
+    fn rename_path_moves_within_approved_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("old.txt");
+        let to = dir.path().join("sub").join("new.txt");
+        fs::write(&from, "content").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+        let mut listings = Vec::new();
+        prefetch_tree(dir.path(), Vec::new(), PREFETCH_MAX_DEPTH, &mut listings);
+        DIR_CACHE.lock().unwrap().extend(listings);
+
+        rename_path(path_str(&from), path_str(&to), false).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "content");
+        let sub = path_str(&dir.path().join("sub"));
+        assert!(cached_tree(&path_str(dir.path())).is_none() && cached_tree(&sub).is_none());
+    }
+
+    #[test]
+    fn rename_path_refuses_existing_destination_without_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("a.txt");
+        let to = dir.path().join("b.txt");
+        fs::write(&from, "a").unwrap();
+        fs::write(&to, "b").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let err = rename_path(path_str(&from), path_str(&to), false).unwrap_err();
+        assert_eq!(err.code, "destination_exists");
+        assert_eq!(fs::read_to_string(&to).unwrap(), "b");
+
+        rename_path(path_str(&from), path_str(&to), true).unwrap();
+        assert_eq!(fs::read_to_string(&to).unwrap(), "a");
+    }
+
+    #[test]
+    fn rename_path_rejects_escaping_the_allowlist() {
+        let approved = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let inside_file = approved.path().join("doc.txt");
+        let outside_file = outside.path().join("doc.txt");
+        fs::write(&inside_file, "inside").unwrap();
+        fs::write(&outside_file, "outside").unwrap();
+        approve_path(path_str(approved.path())).unwrap();
+
+        let err = rename_path(path_str(&inside_file), path_str(&outside.path().join("moved.txt")), false).unwrap_err();
+        assert_eq!(err.code, "destination_not_approved");
+        assert!(inside_file.exists());
+
+        let err = rename_path(path_str(&outside_file), path_str(&approved.path().join("moved.txt")), false).unwrap_err();
+        assert_eq!(err.code, "source_not_approved");
+        assert!(outside_file.exists());
+    }
+
+    #[test]
+    fn copy_then_remove_moves_directory_trees() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("src");
+        fs::create_dir_all(from.join("nested")).unwrap();
+        fs::write(from.join("nested").join("deep.txt"), "deep").unwrap();
+        let to = dir.path().join("dst");
+
+        copy_then_remove(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(to.join("nested").join("deep.txt")).unwrap(), "deep");
+    }
+
+    #[test]
+    fn delete_path_removes_entry_from_cached_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = path_str(dir.path());
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let file = dir.path().join("sub").join("gone.txt");
+        fs::write(&file, "bye").unwrap();
+        fs::write(dir.path().join("sub").join("kept.txt"), "hi").unwrap();
+        approve_path(root.clone()).unwrap();
+
+        // Seed the prefetch cache synchronously; the nested delete leaves the root mtime alone
+        let sub = path_str(&dir.path().join("sub"));
+        let mut listings = Vec::new();
+        prefetch_tree(dir.path(), Vec::new(), PREFETCH_MAX_DEPTH, &mut listings);
+        DIR_CACHE.lock().unwrap().extend(listings);
+        assert_eq!(cached_tree(&sub).unwrap().entries.len(), 2);
+
+        delete_path(path_str(&file), true).unwrap();
+        assert!(!file.exists());
+        assert!(cached_tree(&root).is_none() && cached_tree(&sub).is_none());
+        let listing = list_directory(root.clone(), None, None, None, None).unwrap().entries;
+        assert!(listing[0].children.is_none());
+        let children = expand_directory(sub, None, None, None, None).unwrap().entries;
+        assert_eq!(children.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), ["kept.txt"]);
+
+        delete_path(path_str(&dir.path().join("sub")), true).unwrap();
+        assert!(list_directory(root, None, None, None, None).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn delete_path_rejects_unapproved_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("keep.txt");
+        fs::write(&file, "keep").unwrap();
+
+        let err = delete_path(path_str(&file), true).unwrap_err();
+        assert!(err.starts_with("Access denied"), "unexpected error: {}", err);
+        assert!(file.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_file_replaces_content_and_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("run.sh");
+        fs::write(&target, "echo old\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+        let id = get_file_id(path_str(&target)).unwrap();
+
+        save_file(path_str(&target), "echo new\n".to_string(), None, None, None, None, None).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "echo new\n");
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o750);
+        assert_eq!(get_file_id(path_str(&target)).unwrap(), id);
+
+        save_file_as(path_str(&target), "echo again\n".to_string(), None, None, None).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "echo again\n");
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o750);
+        assert_eq!(get_file_id(path_str(&target)).unwrap(), id);
+
+        // No temp files left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o444)).unwrap();
+        let err = save_file(path_str(&target), "echo forced\n".to_string(), None, None, None, None, None).unwrap_err();
+        assert!(err.message.contains("read-only"), "unexpected error: {}", err.message);
+        assert!(save_file_as(path_str(&target), "echo forced\n".to_string(), None, None, None).is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "echo again\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn read_file_reports_binary_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("image.png");
+        fs::write(&target, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let Err(err) = read_file(path_str(&target), None, None, None, None) else {
+            panic!("binary file was decoded as text");
+        };
+        assert_eq!(err.code, "binary_file");
+    }
+
+    #[test]
+    fn is_binary_sample_flags_control_heavy_invalid_text() {
+        // Latin-1 text is invalid UTF-8 but has no control bytes
+        assert!(!is_binary_sample(b"caf\xe9 cr\xe8me br\xfbl\xe9e\n"));
+        assert!(is_binary_sample(b"\x7fELF\x02\x01\x01\x03\xfe\x04\x05\x06"));
+        assert!(!is_binary_sample(b"\xff\xfeh\0i\0"));
+        // A sample cut mid-character is not invalid
+        assert!(!is_binary_sample("\x01\x02 déjà".as_bytes().split_last().unwrap().1));
+    }
+
+    #[test]
+    fn read_file_refuses_files_over_the_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("huge.log");
+        fs::File::create(&target).unwrap().set_len(DEFAULT_READ_SIZE_LIMIT + 1).unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let Err(err) = read_file(path_str(&target), None, None, None, None) else {
+            panic!("oversized file was read");
+        };
+        assert_eq!(err.code, "file_too_large");
+        assert!(set_read_size_limit(Some(0)).is_err());
+    }
+
+    #[test]
+    fn list_directory_honors_nested_gitignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "dist/\n*.log\n").unwrap();
+        fs::write(root.join(".env"), "KEY=1").unwrap();
+        fs::write(root.join("debug.log"), "").unwrap();
+        fs::create_dir(root.join("dist")).unwrap();
+        fs::create_dir_all(root.join("app").join("cache")).unwrap();
+        fs::write(root.join("app").join(".gitignore"), "cache/\n!keep.log\n").unwrap();
+        fs::write(root.join("app").join("keep.log"), "").unwrap();
+        fs::write(root.join("app").join("trace.log"), "").unwrap();
+        fs::write(root.join("app").join("main.rs"), "").unwrap();
+        approve_path(path_str(root)).unwrap();
+
+        fn names(entries: &[FileEntry]) -> Vec<&str> {
+            entries.iter().map(|e| e.name.as_str()).collect()
+        }
+        let tree = list_directory(path_str(root), None, None, None, None).unwrap().entries;
+        assert_eq!(names(&tree), ["app", ".env", ".gitignore"]);
+        assert!(tree[0].children.is_none());
+        let app = expand_directory(path_str(&root.join("app")), None, None, None, None).unwrap().entries;
+        assert_eq!(names(&app), [".gitignore", "keep.log", "main.rs"]);
+
+        let legacy = list_directory(path_str(root), None, Some(false), None, None).unwrap().entries;
+        assert_eq!(names(&legacy), ["app", "dist", "debug.log"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn list_directory_prefix_hides_what_the_tree_hides() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        fs::create_dir(root.join("node_modules")).unwrap();
+        fs::create_dir(root.join("nested")).unwrap();
+        fs::write(root.join("notes.md"), "").unwrap();
+        fs::write(root.join("noise.log"), "").unwrap();
+        fs::write(root.join("other.txt"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("nested"), root.join("Nav")).unwrap();
+        approve_path(path_str(root)).unwrap();
+
+        let listing = list_directory_prefix(path_str(root), "N".to_string()).unwrap();
+        let found: Vec<_> = listing.entries.iter().map(|e| (e.name.as_str(), e.is_dir)).collect();
+        assert_eq!(found, [("Nav", true), ("nested", true), ("notes.md", false)]);
+        assert!(listing.errors.is_empty());
+    }
+
+    #[test]
+    fn directory_fingerprint_tracks_what_the_tree_shows() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        fs::create_dir(root.join("node_modules")).unwrap();
+        fs::write(root.join("main.rs"), "").unwrap();
+        approve_path(path_str(root)).unwrap();
+        let fingerprint = || directory_fingerprint(path_str(root)).unwrap();
+
+        let before = fingerprint();
+        fs::write(root.join("node_modules").join("dep.js"), "").unwrap();
+        fs::write(root.join("trace.log"), "").unwrap();
+        assert_eq!(fingerprint(), before);
+
+        fs::write(root.join(".env"), "KEY=1").unwrap();
+        assert_ne!(fingerprint(), before);
+    }
+
+    #[test]
+    fn enumerate_source_files_applies_include_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "dist/\n").unwrap();
+        fs::create_dir_all(root.join("dist").join("assets")).unwrap();
+        fs::write(root.join("dist").join("assets").join("app.js"), "").unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src").join("main.rs"), "").unwrap();
+
+        let names = |overrides: &[&str]| {
+            let overrides: Vec<String> = overrides.iter().map(|o| o.to_string()).collect();
+            let (files, truncated) = enumerate_source_files(root, &overrides, SOURCE_FILES_LIMIT).unwrap();
+            assert!(!truncated);
+            files.iter().map(|f| f.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/")).collect::<Vec<_>>()
+        };
+        assert_eq!(names(&[]), ["src/main.rs"]);
+        assert_eq!(names(&["dist"]), ["dist/assets/app.js", "src/main.rs"]);
+        assert_eq!(names(&["dist/**"]), ["dist/assets/app.js", "src/main.rs"]);
+        assert_eq!(names(&["*.css"]), ["src/main.rs"]);
+
+        let (files, truncated) = enumerate_source_files(root, &["dist".to_string()], 1).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn get_file_language_recognizes_special_file_names() {
+        assert_eq!(get_file_language("Dockerfile".to_string()), "dockerfile");
+        assert_eq!(get_file_language("Dockerfile.dev".to_string()), "dockerfile");
+        assert_eq!(get_file_language("Makefile".to_string()), "cmake");
+        assert_eq!(get_file_language("CMakeLists.txt".to_string()), "cmake");
+        assert_eq!(get_file_language("project/Gemfile".to_string()), "ruby");
+        assert_eq!(get_file_language("Gemfile.lock".to_string()), "plaintext");
+        assert_eq!(get_file_language(".gitignore".to_string()), "plaintext");
+        assert_eq!(get_file_language("app.dockerfile".to_string()), "dockerfile");
+        assert_eq!(get_file_language("main.rs".to_string()), "rust");
+    }
+
+    #[test]
+    fn shebang_language_maps_interpreters() {
+        assert_eq!(shebang_language("#!/usr/bin/env python3\n"), Some("python"));
+        assert_eq!(shebang_language("#!/usr/bin/python3.11"), Some("python"));
+        assert_eq!(shebang_language("#!/bin/bash -e\n"), Some("shell"));
+        assert_eq!(shebang_language("#!/usr/bin/env -S node --no-warnings"), Some("javascript"));
+        assert_eq!(shebang_language("#!/usr/bin/perl -w"), Some("perl"));
+        assert_eq!(shebang_language("#!/usr/bin/env ruby"), Some("ruby"));
+        assert_eq!(shebang_language("#!/usr/bin/env wat"), None);
+        assert_eq!(shebang_language("print('no shebang')"), None);
+    }
+
+    #[test]
+    fn revoke_path_removes_folder_and_nested_approvals() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let file = dir.path().join("sub").join("notes.txt");
+        fs::write(&file, "notes").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+        approve_path(path_str(&file)).unwrap();
+        assert!(read_file(path_str(&file), None, None, None, None).is_ok());
+        let sub = path_str(&dir.path().join("sub"));
+        prefetch_directory(sub.clone()).unwrap();
+        let watch_id = format!("prefetch:{}", sub);
+        assert!(WATCHERS.lock().unwrap().contains_key(&watch_id));
+
+        let mut listings = Vec::new();
+        prefetch_tree(dir.path(), Vec::new(), PREFETCH_MAX_DEPTH, &mut listings);
+        DIR_CACHE.lock().unwrap().extend(listings);
+
+        assert!(revoke_path(path_str(dir.path())).unwrap());
+        assert!(!WATCHERS.lock().unwrap().contains_key(&watch_id));
+        assert!(cached_tree(&path_str(dir.path())).is_none() && cached_tree(&sub).is_none());
+        let Err(err) = read_file(path_str(&file), None, None, None, None) else {
+            panic!("revoked file is still readable");
+        };
+        assert!(err.message.starts_with("Access denied"), "unexpected error: {}", err.message);
+        assert!(!revoke_path(path_str(dir.path())).unwrap());
+    }
+
+    #[test]
+    fn is_generated_file_checks_vendored_dirs_below_the_root_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("vendor").join("app");
+        fs::create_dir_all(project.join("third_party")).unwrap();
+        fs::write(project.join("main.rs"), "fn main() {}\n").unwrap();
+        fs::write(project.join("third_party").join("lib.rs"), "pub fn f() {}\n").unwrap();
+        approve_path(path_str(&project)).unwrap();
+
+        assert!(!is_generated_file(path_str(&project.join("main.rs"))).unwrap());
+        assert!(is_generated_file(path_str(&project.join("third_party").join("lib.rs"))).unwrap());
+    }
+
+    #[test]
+    fn test_write_access_skips_leftover_probes() {
+        let dir = tempfile::tempdir().unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+        // Squat on the next few probe names, as a crashed run reusing this PID would
+        let next = NEXT_TEMP_ID.load(Ordering::Relaxed);
+        for id in next..next + 4 {
+            fs::write(dir.path().join(format!(".crabtree-write-test-{}-{}", std::process::id(), id)), "").unwrap();
+        }
+
+        assert!(test_write_access(path_str(dir.path())).unwrap());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn load_allowlist_drops_stale_entries_and_repairs_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let kept = fs::canonicalize(dir.path()).unwrap().join("kept");
+        let swapped = fs::canonicalize(dir.path()).unwrap().join("swapped");
+        let missing = fs::canonicalize(dir.path()).unwrap().join("missing");
+        fs::create_dir(&kept).unwrap();
+        std::os::unix::fs::symlink(outside.path(), &swapped).unwrap();
+        let saved = dir.path().join("config").join(ALLOWLIST_FILE);
+        fs::create_dir(saved.parent().unwrap()).unwrap();
+        fs::write(&saved, serde_json::to_string(&[&kept, &swapped, &missing]).unwrap()).unwrap();
+
+        load_allowlist(saved.clone()).unwrap();
+        let persisted = fs::read_to_string(&saved).unwrap();
+        fs::write(&saved, "{ not json").unwrap();
+        load_allowlist(saved.clone()).unwrap();
+        let repaired = fs::read_to_string(&saved);
+        // Stop persisting before other tests approve anything
+        *ALLOWLIST_PATH.lock().unwrap() = None;
+
+        let allowed = APPROVED_PATHS.lock().unwrap().clone();
+        assert!(allowed.contains(&kept));
+        assert!(!allowed.contains(&swapped) && !allowed.contains(&fs::canonicalize(outside.path()).unwrap()));
+        let persisted: Vec<PathBuf> = serde_json::from_str(&persisted).unwrap();
+        assert!(persisted.contains(&kept) && !persisted.contains(&swapped) && !persisted.contains(&missing));
+        assert!(serde_json::from_str::<Vec<PathBuf>>(&repaired.unwrap()).is_ok());
+    }
+
+    #[test]
+    fn remap_paths_follows_rename_but_not_arbitrary_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("proj");
+        fs::create_dir_all(project.join("old")).unwrap();
+        let file = project.join("old").join("notes.txt");
+        fs::write(&file, "notes").unwrap();
+        approve_path(path_str(&project)).unwrap();
+        approve_path(path_str(&file)).unwrap();
+        fs::rename(project.join("old"), project.join("new")).unwrap();
+
+        let remapped = remap_paths(path_str(&project.join("old")), path_str(&project.join("new"))).unwrap();
+        assert_eq!(remapped.len(), 1);
+        assert!(remapped[0].1.ends_with("notes.txt"));
+
+        // A deleted single-file approval cannot be pointed at a folder beside it
+        let secret = dir.path().join("secretdir");
+        fs::create_dir(&secret).unwrap();
+        fs::write(secret.join("key"), "key").unwrap();
+        let lone = dir.path().join("a.txt");
+        fs::write(&lone, "a").unwrap();
+        approve_path(path_str(&lone)).unwrap();
+        fs::remove_file(&lone).unwrap();
+
+        let err = remap_paths(path_str(&lone), path_str(&secret)).unwrap_err();
+        assert!(err.starts_with("Access denied"), "unexpected error: {}", err);
+        assert!(read_file(path_str(&secret.join("key")), None, None, None, None).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_path_allowed_rejects_symlinked_directory_escape() {
+        let approved = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), approved.path().join("escape")).unwrap();
+        approve_path(path_str(approved.path())).unwrap();
+
+        let root = fs::canonicalize(approved.path()).unwrap();
+        let through_link = root.join("escape").join("secret.txt");
+        let err = is_path_allowed(&path_str(&through_link)).unwrap_err();
+        assert!(err.contains("through a symlink"), "unexpected error: {}", err);
+        let Err(err) = read_file(path_str(&through_link), None, None, None, None) else {
+            panic!("read through an escaping symlink");
+        };
+        assert!(err.message.starts_with("Access denied"), "unexpected error: {}", err.message);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_path_allowed_rejects_root_replaced_by_symlink() {
+        let parent = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        let project = parent.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("notes.txt"), "notes").unwrap();
+        approve_path(path_str(&project)).unwrap();
+        assert!(is_path_allowed(&path_str(&project.join("notes.txt"))).is_ok());
+
+        fs::remove_dir_all(&project).unwrap();
+        std::os::unix::fs::symlink(outside.path(), &project).unwrap();
+        let err = is_path_allowed(&path_str(&project.join("secret.txt"))).unwrap_err();
+        assert!(err.starts_with("Access denied"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn save_file_detects_out_of_band_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("shared.txt");
+        fs::write(&target, "original").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+        let opened = read_file(path_str(&target), None, None, None, None).unwrap();
+
+        // Another program edits the file while it is open
+        fs::write(&target, "external edit").unwrap();
+
+        let err = save_file(path_str(&target), "mine".to_string(), None, None, None, Some(opened.hash.clone()), None).unwrap_err();
+        assert_eq!(err.code, "conflict");
+        assert_eq!(fs::read_to_string(&target).unwrap(), "external edit");
+
+        save_file(path_str(&target), "mine".to_string(), None, None, None, Some(opened.hash), Some(true)).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "mine");
+
+        let reopened = read_file(path_str(&target), None, None, None, None).unwrap();
+        save_file(path_str(&target), "mine again".to_string(), None, None, None, Some(reopened.hash), None).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "mine again");
+    }
+
+    #[test]
+    fn read_file_honors_encoding_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("short.txt");
+        let (bytes, _, _) = encoding_rs::WINDOWS_1251.encode("Привет");
+        fs::write(&target, &bytes).unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let file = read_file(path_str(&target), None, None, None, Some("windows-1251".to_string())).unwrap();
+        assert_eq!(file.encoding, "windows-1251");
+        assert_eq!(file.content, "Привет");
+
+        let Err(err) = read_file(path_str(&target), None, None, None, Some("martian".to_string())) else {
+            panic!("unknown encoding label was accepted");
+        };
+        assert_eq!(err.message, "Unknown encoding: martian");
+    }
+
+    #[test]
+    fn list_directory_applies_list_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src").join("nested")).unwrap();
+        fs::write(root.join("src").join("nested").join("deep.rs"), "").unwrap();
+        fs::write(root.join("bundle.min.js"), "").unwrap();
+        fs::write(root.join(".env"), "").unwrap();
+        approve_path(path_str(root)).unwrap();
+
+        let options = ListOptions {
+            max_depth: Some(2),
+            show_hidden: Some(false),
+            extra_ignores: vec!["*.min.js".to_string()],
+            ..ListOptions::default()
+        };
+        let tree = list_directory(path_str(root), None, None, None, Some(options)).unwrap().entries;
+        assert_eq!(tree.len(), 1);
+        let nested = &tree[0].children.as_ref().unwrap()[0];
+        assert_eq!(nested.name, "nested");
+        assert_eq!(nested.children.as_ref().unwrap()[0].name, "deep.rs");
+
+        let defaults = list_directory(path_str(root), None, None, None, None).unwrap().entries;
+        assert_eq!(defaults.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), ["src", ".env", "bundle.min.js"]);
+        assert!(defaults[0].children.is_none());
+
+        fs::create_dir(root.join("target")).unwrap();
+        let with_target = ListOptions { show_always_skipped: true, ..ListOptions::default() };
+        let tree = list_directory(path_str(root), None, None, None, Some(with_target)).unwrap().entries;
+        assert_eq!(tree.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), ["src", "target", ".env", "bundle.min.js"]);
+    }
+
+    #[test]
+    fn create_file_and_directory_appear_in_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = path_str(dir.path());
+        approve_path(root.clone()).unwrap();
+
+        create_file(path_str(&dir.path().join("notes.txt"))).unwrap();
+        create_directory(path_str(&dir.path().join("docs"))).unwrap();
+        assert_eq!(fs::read(dir.path().join("notes.txt")).unwrap(), b"");
+
+        let listing = list_directory(root, None, None, None, None).unwrap().entries;
+        let entries: Vec<_> = listing.iter().map(|e| (e.name.as_str(), e.is_dir)).collect();
+        assert_eq!(entries, [("docs", true), ("notes.txt", false)]);
+
+        let err = create_file(path_str(&dir.path().join("notes.txt"))).unwrap_err();
+        assert_eq!(err.code, "already_exists");
+        let err = create_directory(path_str(&dir.path().join("docs"))).unwrap_err();
+        assert_eq!(err.code, "already_exists");
+        let Err(err) = create_and_open(path_str(&dir.path().join("notes.txt")), "x".to_string()) else {
+            panic!("create_and_open replaced an existing file");
+        };
+        assert_eq!(err.code, "already_exists");
+        let err = create_directory(path_str(&dir.path().join("missing").join("child"))).unwrap_err();
+        assert_ne!(err.code, "already_exists");
+        assert!(!dir.path().join("missing").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn list_directory_reports_symlink_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = path_str(dir.path());
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub").join("a.txt"), "a").unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("sub").join("loop")).unwrap();
+        approve_path(root.clone()).unwrap();
+
+        let options = ListOptions { max_depth: Some(5), ..ListOptions::default() };
+        let listing = list_directory(root, None, None, None, Some(options)).unwrap();
+        let sub = &listing.entries[0];
+        let children = sub.children.as_ref().unwrap();
+        assert_eq!(children.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), ["a.txt", "loop"]);
+        assert!(children[1].is_dir && children[1].children.is_none());
+        assert_eq!(listing.errors.len(), 1);
+        assert!(listing.errors[0].path.ends_with("loop"));
+        assert!(listing.errors[0].reason.starts_with("Symlink cycle"));
+    }
+
+    #[test]
+    fn decode_with_policy_reports_offsets_per_policy() {
+        let bytes = b"ab\xffcd\xfe";
+        let (kept, errors) = decode_with_policy(bytes, encoding_rs::UTF_8, ReplacementPolicy::Keep);
+        assert_eq!(kept, "ab\u{FFFD}cd\u{FFFD}");
+        assert_eq!(errors, [2, 7]);
+        let (stripped, errors) = decode_with_policy(bytes, encoding_rs::UTF_8, ReplacementPolicy::Strip);
+        assert_eq!(stripped, "abcd");
+        assert_eq!(errors, [2, 4]);
+
+        let mut offsets = vec![2, 4];
+        let expanded = expand_tab_stops("a\tcd", 4, &mut offsets);
+        assert_eq!(expanded, "a   cd");
+        assert_eq!(offsets, [4, 6]);
+        assert_eq!(byte_offsets_to_utf16("😀é\u{FFFD}", &[6, 9]), [3, 4]);
+    }
+
+    #[test]
+    fn decode_errors_follow_nfc_and_tab_expansion() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("mixed.txt");
+        let mut bytes = "😀\tx".as_bytes().to_vec();
+        bytes.push(0xFF);
+        bytes.extend("e\u{301}\t|".as_bytes());
+        fs::write(&target, &bytes).unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let file = read_file(path_str(&target), Some(4), Some("mark".to_string()), Some(true), Some("UTF-8".to_string())).unwrap();
+        assert_eq!(file.content, "😀   x\u{27E6}\u{FFFD}\u{27E7}é   |");
+        assert!(file.unicode_normalized);
+        assert_eq!(file.expanded_tab_width, Some(4));
+        // UTF-16 offset of the opening mark: the emoji is two code units
+        assert_eq!(file.decode_errors, [6]);
+    }
+
+    #[test]
+    fn read_file_delta_reports_line_hunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("notes.txt");
+        fs::write(&target, "a\nB\nc\nd\n").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let delta = read_file_delta(path_str(&target), String::new(), Some("a\nb\nc\n".to_string())).unwrap();
+        assert!(delta.changed);
+        assert_eq!(delta.content.as_deref(), Some("a\nB\nc\nd\n"));
+        let hunks: Vec<_> = delta
+            .hunks
+            .iter()
+            .map(|h| (h.old_start, h.old_lines, h.new_start, h.new_lines, h.lines.concat()))
+            .collect();
+        assert_eq!(hunks, [(2, 1, 2, 1, "B\n".to_string()), (4, 0, 4, 1, "d\n".to_string())]);
+
+        let unchanged = read_file_delta(path_str(&target), delta.hash.to_uppercase(), None).unwrap();
+        assert!(!unchanged.changed && unchanged.content.is_none());
+    }
+
+    #[test]
+    fn parse_env_line_handles_quoting_and_comments() {
+        let parsed = |line| parse_env_line(line).map(|(_, value)| value);
+        assert_eq!(parse_env_line("export API_KEY=abc"), Some(("API_KEY".to_string(), "abc".to_string())));
+        assert_eq!(parsed(r#"GREETING="say \"hi\"\tthen\nleave""#).as_deref(), Some("say \"hi\"\tthen\nleave"));
+        assert_eq!(parsed(r"RAW='no \n # escapes'").as_deref(), Some(r"no \n # escapes"));
+        assert_eq!(parsed("NAME=value # trailing comment").as_deref(), Some("value"));
+        assert_eq!(parsed("COLOR=#fff").as_deref(), Some("#fff"));
+        assert_eq!(parsed(r#"BROKEN="unterminated"#), None);
+        assert_eq!(parsed("# just a comment"), None);
+        assert_eq!(parsed("=value"), None);
+        assert_eq!(parsed("BAD KEY=value"), None);
+    }
+
+    #[test]
+    fn relative_paths_render_as_imports() {
+        let import = |base: &str, target: &str| {
+            relative_path(Path::new(base), Path::new(target)).map(|rel| import_path_form(&rel))
+        };
+        assert_eq!(import("/src/app", "/src/utils/helper.ts").as_deref(), Some("../utils/helper.ts"));
+        assert_eq!(import("/src/app", "/src/app/sibling.ts").as_deref(), Some("./sibling.ts"));
+        assert_eq!(import("/src/app/deep", "/src").as_deref(), Some("../.."));
+        assert_eq!(import("src", "lib"), None);
+    }
+
+    #[test]
+    fn format_and_minify_json_keep_numbers_verbatim() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("data.json");
+        fs::write(&target, r#"{"big": 12345678901234567890123, "exp": 1E2, "small": 0.1}"#).unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        // Exact digits survive; only the exponent's spelling is normalized
+        let pretty = format_json(path_str(&target), 2).unwrap();
+        assert_eq!(pretty, "{\n  \"big\": 12345678901234567890123,\n  \"exp\": 1e+2,\n  \"small\": 0.1\n}");
+        let minified = minify_json(path_str(&target)).unwrap();
+        assert_eq!(minified, r#"{"big":12345678901234567890123,"exp":1e+2,"small":0.1}"#);
+    }
+
+    #[test]
+    fn split_file_writes_numbered_parts_with_bom_and_endings() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("notes.txt");
+        fs::write(&source, "\u{FEFF}a\r\nb\r\nc\r\n").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let parts = split_file(path_str(&source), 2).unwrap();
+        let first = dir.path().join("notes.part001.txt");
+        let second = dir.path().join("notes.part002.txt");
+        assert_eq!(parts, [path_str(&first), path_str(&second)]);
+        assert_eq!(fs::read(&first).unwrap(), "\u{FEFF}a\r\nb\r\n".as_bytes());
+        assert_eq!(fs::read(&second).unwrap(), "\u{FEFF}c\r\n".as_bytes());
+
+        // One existing part blocks the whole split, before anything is written
+        fs::remove_file(&first).unwrap();
+        let err = split_file(path_str(&source), 2).unwrap_err();
+        assert!(err.starts_with("Output file already exists"), "unexpected error: {}", err);
+        assert!(!first.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_link_makes_hard_and_symbolic_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("data.txt");
+        fs::write(&source, "payload").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let hard = dir.path().join("hard.txt");
+        create_link(path_str(&source), path_str(&hard), false).unwrap();
+        assert!(!fs::symlink_metadata(&hard).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&hard).unwrap(), "payload");
+
+        let soft = dir.path().join("soft.txt");
+        create_link(path_str(&source), path_str(&soft), true).unwrap();
+        assert_eq!(fs::read_link(&soft).unwrap(), fs::canonicalize(&source).unwrap());
+
+        let err = create_link(path_str(&source), path_str(&soft), true).unwrap_err();
+        assert!(err.starts_with("Link path already exists"), "unexpected error: {}", err);
+        let err = create_link(path_str(dir.path()), path_str(&dir.path().join("dir-link")), false).unwrap_err();
+        assert_eq!(err, "Hard links to directories are not supported");
+    }
+
+    #[test]
+    fn save_file_unique_picks_the_first_free_name() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("draft.md"), "taken").unwrap();
+        fs::write(dir.path().join("draft-1.md"), "taken").unwrap();
+        approve_path(path_str(dir.path())).unwrap();
+
+        let saved = save_file_unique(path_str(dir.path()), "draft.md".to_string(), "fresh".to_string()).unwrap();
+        assert_eq!(saved, path_str(&dir.path().join("draft-2.md")));
+        assert_eq!(fs::read_to_string(&saved).unwrap(), "fresh");
+        assert_eq!(fs::read_to_string(dir.path().join("draft.md")).unwrap(), "taken");
+
+        let saved = save_file_unique(path_str(dir.path()), "README".to_string(), "first".to_string()).unwrap();
+        assert_eq!(saved, path_str(&dir.path().join("README")));
+        assert!(save_file_unique(path_str(dir.path()), "../escape.md".to_string(), String::new()).is_err());
+    }
+}
@@ -59,6 +59,7 @@ const state = {
   sidebarOpen: true,
   folderPath: null,
   folderEntries: null,
+  folderFiles: null,
   untitledCounter: 0,
   wordWrap: localStorage.getItem('crabtree-wordwrap') === 'true',
   fontSize: parseInt(localStorage.getItem('crabtree-fontsize')) || 14,
@@ -174,9 +175,12 @@ async function restoreSession() {
     if (session.folderPath) {
       try {
         state.folderPath = session.folderPath;
-        const entries = await invoke('list_directory', { path: session.folderPath });
+        const { entries, errors } = await invoke('list_directory', { path: session.folderPath });
+        if (errors.length) console.warn('Some folder entries could not be read:', errors);
         state.folderEntries = entries;
         renderFileTree(entries);
+        // Not awaited: the finder index fills in while the tabs restore
+        loadFolderFiles(session.folderPath);
       } catch { /* folder may no longer exist */ }
     }
 
@@ -2107,15 +2111,34 @@ async function openFolder() {
     const folderPath = typeof selected === 'string' ? selected : selected.path;
     await invoke('approve_path', { path: folderPath }).catch(err => console.warn('Failed to approve path:', err));
     state.folderPath = folderPath;
-    const entries = await invoke('list_directory', { path: folderPath });
+    const { entries, errors } = await invoke('list_directory', { path: folderPath });
+    if (errors.length) console.warn('Some folder entries could not be read:', errors);
     state.folderEntries = entries;
     renderFileTree(entries);
     trustManager.setCurrentWorktree(folderPath);
     updateTrustBadge();
+    // The tree is already usable; extension discovery needs the full file list
+    await loadFolderFiles(folderPath);
     await loadWorkspaceExtensions();
   } catch (err) { console.error('Open folder error:', err); }
 }
 
+/**
+ * The sidebar tree only holds expanded folders, so the file finder and
+ * extension discovery use a flat listing of every file in the folder instead.
+ */
+async function loadFolderFiles(folderPath) {
+  try {
+    const { files, truncated } = await invoke('list_source_files', { root: folderPath });
+    if (truncated) console.warn(`Folder has more files than the finder indexes; showing the first ${files.length}`);
+    // A folder opened meanwhile owns the index now
+    if (state.folderPath === folderPath) state.folderFiles = files;
+  } catch (err) {
+    console.warn('Failed to list folder files:', err);
+    state.folderFiles = [];
+  }
+}
+
 function renderFileTree(entries, container, depth) {
   if (depth === undefined) depth = 0;
   if (!container) {
@@ -2141,13 +2164,20 @@ function renderFileTree(entries, container, depth) {
 
       const childContainer = document.createElement('div');
       childContainer.className = 'tree-children';
-      item.addEventListener('click', (e) => {
+      item.addEventListener('click', async (e) => {
         e.stopPropagation();
         arrow.classList.toggle('expanded');
         childContainer.classList.toggle('expanded');
-        if (childContainer.classList.contains('expanded') && !childContainer.children.length && entry.children) {
-          renderFileTree(entry.children, childContainer, depth + 1);
+        if (!childContainer.classList.contains('expanded') || childContainer.children.length) return;
+        if (!entry.children) {
+          try {
+            const { entries, errors } = await invoke('expand_directory', { path: entry.path });
+            if (errors.length) console.warn('Some folder entries could not be read:', errors);
+            entry.children = entries;
+          }
+          catch (err) { console.error('Expand folder error:', err); return; }
         }
+        renderFileTree(entry.children, childContainer, depth + 1);
       });
       container.append(item, childContainer);
     } else {
@@ -2622,14 +2652,6 @@ function collectTabDiagnostics(tab) {
   return diagnostics.filter((d) => passesDiagnosticsFilter(d.severity));
 }
 
-function flattenEntries(entries, out = []) {
-  if (!entries) return out;
-  for (const e of entries) {
-    out.push(e);
-    if (e.is_dir && e.children) flattenEntries(e.children, out);
-  }
-  return out;
-}
 
 function unloadWorkspaceExtensions() {
   const cmds = extensionHost.getCommands();
@@ -2650,10 +2672,8 @@ async function loadWorkspaceExtensions() {
     return;
   }
   try {
-    const entries = flattenEntries(state.folderEntries, []);
-    const manifests = entries
-      .filter((e) => !e.is_dir && (e.name.endsWith('.crabext.json') || e.name.endsWith('.crabtree-ext.json')))
-      .map((e) => e.path);
+    const manifests = (state.folderFiles || [])
+      .filter((p) => p.endsWith('.crabext.json') || p.endsWith('.crabtree-ext.json'));
 
     if (manifests.length === 0) return;
     const loaded = await extensionHost.loadFromFilePaths(manifests);
@@ -3448,18 +3468,14 @@ commandPalette.register('tab:close_all', 'Tab: Close All Tabs', () => closeAllTa
 // ─── Fuzzy File Finder (Ctrl+P) ───
 let fileFinderOpen = false;
 
-function collectAllFiles(entries, base = '') {
-  const files = [];
-  if (!entries) return files;
-  for (const e of entries) {
-    const path = base ? `${base}/${e.name}` : e.name;
-    if (e.is_dir && e.children) {
-      files.push(...collectAllFiles(e.children, path));
-    } else if (!e.is_dir) {
-      files.push({ name: e.name, path: e.path || path, displayPath: path });
-    }
-  }
-  return files;
+function collectAllFiles(paths, root) {
+  const prefix = root.replace(/[\\/]+$/, '');
+  return paths.map((path) => {
+    const displayPath = path.startsWith(prefix)
+      ? path.slice(prefix.length).replace(/^[\\/]+/, '').replace(/\\/g, '/')
+      : path;
+    return { name: displayPath.split('/').pop(), path, displayPath };
+  });
 }
 
 function toggleFileFinder() {
@@ -3520,8 +3536,8 @@ function renderFileFinderResults(query, selIdx = 0) {
   for (const tab of state.tabs) {
     candidates.push({ id: `tab:${tab.id}`, name: tab.name, displayPath: tab.path || tab.name, type: 'tab', tabId: tab.id });
   }
-  if (state.folderEntries) {
-    for (const f of collectAllFiles(state.folderEntries)) {
+  if (state.folderFiles && state.folderPath) {
+    for (const f of collectAllFiles(state.folderFiles, state.folderPath)) {
       if (!candidates.find(c => c.displayPath === f.path)) {
         candidates.push({ id: `file:${f.path}`, name: f.name, displayPath: f.displayPath, type: 'file', path: f.path });
       }